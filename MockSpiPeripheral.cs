@@ -45,6 +45,41 @@ namespace Antmicro.Renode.Peripherals.SPI
                             state = State.ReadRegAddr;
                             break;
 
+                        case Command.WhoAmI:
+                            currentCommand = Command.WhoAmI;
+                            state = State.WhoAmIValue;
+                            break;
+
+                        case Command.Dump:
+                            currentCommand = Command.Dump;
+                            dumpAddr = 0;
+                            state = State.DumpAddr;
+                            break;
+
+                        case Command.Fill:
+                            currentCommand = Command.Fill;
+                            fillStart = 0;
+                            fillCount = 0;
+                            state = State.FillStart;
+                            break;
+
+                        case Command.Ping:
+                            currentCommand = Command.Ping;
+                            pingToken = 0;
+                            state = State.PingToken;
+                            break;
+
+                        case Command.SelfTest:
+                            currentCommand = Command.SelfTest;
+                            state = State.SelfTestResult;
+                            break;
+
+                        case Command.Reset:
+                            currentCommand = Command.Reset;
+                            Array.Clear(registers, 0, registers.Length);
+                            LogDebug("Reset: register file cleared");
+                            break;
+
                         default:
                             LogError($"Unknown command byte 0x{data:X2}");
                             state = State.Error;
@@ -107,6 +142,67 @@ namespace Antmicro.Renode.Peripherals.SPI
                     state = State.Idle;
                     return response;
 
+                case State.WhoAmIValue:
+                    state = State.Idle;
+                    LogDebug($"WhoAmI: returning 0x{WhoAmIValue:X2}");
+                    return WhoAmIValue;
+
+                case State.DumpAddr:
+                    dumpAddr = data;
+                    state = State.DumpData;
+                    LogDebug($"Dump: start = 0x{dumpAddr:X2}");
+                    return 0x0;
+
+                case State.DumpData:
+                    // Out-of-range addresses read as 0xFF, like ReadReg.
+                    response = dumpAddr < RegisterFileSize ? registers[dumpAddr] : (byte)0xFF;
+                    // Byte arithmetic: 0xFF wraps round to 0x00.
+                    dumpAddr++;
+                    return response;
+
+                case State.FillStart:
+                    fillStart = data;
+                    state = State.FillCount;
+                    return 0x0;
+
+                case State.FillCount:
+                    fillCount = data;
+                    state = State.FillValue;
+                    return 0x0;
+
+                case State.FillValue:
+                    for (int i = 0; i < fillCount; i++)
+                    {
+                        // Same byte wrap-around as Dump; addresses past the
+                        // register file are dropped.
+                        byte addr = (byte)(fillStart + i);
+                        if (addr < RegisterFileSize)
+                        {
+                            registers[addr] = data;
+                        }
+                    }
+                    LogDebug($"Fill: {fillCount} registers from 0x{fillStart:X2} = 0x{data:X2}");
+                    state = State.Idle;
+                    return 0x0;
+
+                case State.PingToken:
+                    pingToken = data;
+                    state = State.PingReply;
+                    return 0x0;
+
+                case State.PingReply:
+                    // XORed so a plain loopback can't answer for us.
+                    response = (byte)(pingToken ^ PingXor);
+                    state = State.Idle;
+                    LogDebug($"Ping: token 0x{pingToken:X2}, returning 0x{response:X2}");
+                    return response;
+
+                case State.SelfTestResult:
+                    state = State.Idle;
+                    response = RunSelfTest() ? SelfTestPass : (byte)0x00;
+                    LogDebug($"SelfTest: returning 0x{response:X2}");
+                    return response;
+
                 case State.Error:
                     return 0xFF;
 
@@ -132,6 +228,22 @@ namespace Antmicro.Renode.Peripherals.SPI
             LogDebug("Peripheral reset");
         }
 
+        // Internal consistency check for Command.SelfTest: the register file
+        // has its documented size, a register holds what's written to it,
+        // and no echo byte is left over.  Register 0 is borrowed and put back.
+        private bool RunSelfTest()
+        {
+            bool ok = registers.Length == RegisterFileSize && echoBuffer.Count == 0;
+            byte saved = registers[0];
+            foreach (byte pattern in new byte[] { 0xA5, 0x5A })
+            {
+                registers[0] = pattern;
+                ok &= registers[0] == pattern;
+            }
+            registers[0] = saved;
+            return ok;
+        }
+
         private void LogDebug(string msg)
         {
             machine?.Log(LogLevel.Debug, "[MockSpiPeripheral] " + msg);
@@ -147,7 +259,13 @@ namespace Antmicro.Renode.Peripherals.SPI
             None = 0x0,
             Echo = 0x1,
             WriteReg = 0x2,
-            ReadReg = 0x3
+            ReadReg = 0x3,
+            WhoAmI = 0x4,
+            Reset = 0x5,
+            Dump = 0x6,
+            Fill = 0x7,
+            Ping = 0x8,
+            SelfTest = 0x9
         }
 
         private enum State 
@@ -158,10 +276,25 @@ namespace Antmicro.Renode.Peripherals.SPI
             WriteRegValue,
             ReadRegAddr,
             ReadRegValue,
+            WhoAmIValue,
+            DumpAddr,
+            DumpData,
+            FillStart,
+            FillCount,
+            FillValue,
+            PingToken,
+            PingReply,
+            SelfTestResult,
             Error,
         }
 
         private const int RegisterFileSize = 16;
+        // Must match WHO_AM_I_ID in src/mock_spi.rs
+        private const byte WhoAmIValue = 0x4D;
+        // Must match PING_XOR in src/mock_spi.rs
+        private const byte PingXor = 0xA5;
+        // Must match SELF_TEST_PASS in src/mock_spi.rs
+        private const byte SelfTestPass = 0x5A;
 
         private readonly IMachine machine;
         private readonly byte[] registers;
@@ -173,5 +306,9 @@ namespace Antmicro.Renode.Peripherals.SPI
         private byte writeAddr;
         private byte writeValue;
         private byte readAddr;
+        private byte dumpAddr;
+        private byte fillStart;
+        private byte fillCount;
+        private byte pingToken;
     }
 }
\ No newline at end of file
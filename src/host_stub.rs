@@ -0,0 +1,538 @@
+//! Host-side stand-in for the Renode mock, used by `cargo test` on the host.
+//!
+//! `ProtocolStub` implements `SpiDevice<W>` and models
+//! `MockSpiPeripheral.cs` word for word: the first word after CS asserts is
+//! decoded as a `Command`, each following word gets the same response the C#
+//! mock would clock out, and the end of a transaction resets the state
+//! machine just like `FinishTransmission()`.
+//!
+//! The one deliberate difference is the register file: 256 entries instead
+//! of the mock's 16, so every `u8` address is valid.  Registers are `W`
+//! wide, so a `u16` stub stores whole words.
+//!
+//! Every operation's transmitted words are appended to `log()`, so tests
+//! can assert the exact framing a driver method puts on the wire.
+//!
+//! `with_crc(true)` switches register commands to the CRC-8 framing used by
+//! `MockSpiDriver::with_crc`, which the C# mock doesn't implement.
+//!
+//! `CaptureSpiDevice` is the protocol-agnostic alternative: it records the
+//! bytes on the wire and plays back whatever RX bytes a test scripts.
+//!
+//! `FaultyStub` wraps a `ProtocolStub` and fails transactions on a
+//! schedule, for exercising drivers' error paths.
+
+use core::convert::Infallible;
+use std::collections::VecDeque;
+
+use embedded_hal::spi::{ErrorKind, ErrorType, Operation, SpiDevice};
+
+use crate::mock_spi::{Command, PING_XOR, SELF_TEST_PASS, WHO_AM_I_ID, Word};
+
+pub const REGISTER_FILE_SIZE: usize = 256;
+
+const ECHO: u8 = Command::Echo as u8;
+const WRITE_REG: u8 = Command::WriteReg as u8;
+const READ_REG: u8 = Command::ReadReg as u8;
+const WHO_AM_I: u8 = Command::WhoAmI as u8;
+const RESET: u8 = Command::Reset as u8;
+const DUMP: u8 = Command::Dump as u8;
+const FILL: u8 = Command::Fill as u8;
+const PING: u8 = Command::Ping as u8;
+const SELF_TEST: u8 = Command::SelfTest as u8;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    Idle,
+    EchoPayload,
+    WriteRegAddr,
+    WriteRegValue,
+    WriteRegCrc,
+    ReadRegAddr,
+    ReadRegValue,
+    ReadRegCrc,
+    WhoAmIValue,
+    DumpAddr,
+    DumpData,
+    FillStart,
+    FillCount,
+    FillValue,
+    PingToken,
+    PingReply,
+    SelfTestResult,
+    Error,
+}
+
+pub struct ProtocolStub<W = u8> {
+    pub registers: [W; REGISTER_FILE_SIZE],
+    state: State,
+    /// The C# echo buffer never holds more than one word: each payload word
+    /// pops the previous one and pushes itself.
+    echo_pending: Option<W>,
+    addr: W,
+    value: W,
+    crc: bool,
+    /// Flip the CRC returned by reads, to exercise the mismatch path.
+    pub corrupt_crc: bool,
+    /// Addresses that ignore `WriteReg`, like a read-only status register.
+    pub read_only: &'static [u8],
+    /// Number of `ReadReg` values served so far.
+    pub reads: u32,
+    /// Rewrites each `ReadReg` response: `(addr, reads, stored) -> value`,
+    /// where `reads` counts this one.  Lets tests script status registers.
+    pub on_read: Option<fn(u8, u32, W) -> W>,
+    /// Clocked back under a `ReadReg` address – where a real device might
+    /// report status (`read_reg_raw()[1]`).  The C# mock always sends 0.
+    pub read_header: W,
+    /// Clock `WriteReg`'s opcode back under the address, as the ack
+    /// `write_reg_ack` checks.  The C# mock always sends 0.
+    pub ack: bool,
+    /// Make `SelfTest` report a failure, as a broken mock would.
+    pub self_test_fails: bool,
+    /// `Operation::DelayNs` durations, in order.  Delays aren't logged.
+    pub delays: Vec<u32>,
+    /// Transactions run so far, i.e. CS assertions.
+    pub transactions: u32,
+    /// TX words of every operation processed, one entry per `Operation`.
+    log: Vec<Vec<W>>,
+}
+
+impl ProtocolStub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<W: Word> ProtocolStub<W> {
+    pub fn with_crc(mut self, enabled: bool) -> Self {
+        self.crc = enabled;
+        self
+    }
+
+    /// TX words of each operation so far, oldest first.  Reads log the
+    /// dummy words clocked out.
+    pub fn log(&self) -> &[Vec<W>] {
+        &self.log
+    }
+
+    /// The opcode carried by `data`, or `None` if it doesn't fit in a byte.
+    fn opcode(data: W) -> Option<u8> {
+        let op = data.low_byte();
+        (W::from_u8(op) == data).then_some(op)
+    }
+
+    fn store(&mut self, addr: u8, value: W) {
+        if !self.read_only.contains(&addr) {
+            self.registers[addr as usize] = value;
+        }
+    }
+
+    /// Equivalent of `MockSpiPeripheral.Transmit()`.
+    fn exchange(&mut self, data: W) -> W {
+        match self.state {
+            State::Idle => {
+                self.state = match Self::opcode(data) {
+                    Some(ECHO) => State::EchoPayload,
+                    Some(WRITE_REG) => State::WriteRegAddr,
+                    Some(READ_REG) => State::ReadRegAddr,
+                    Some(WHO_AM_I) => State::WhoAmIValue,
+                    Some(DUMP) => State::DumpAddr,
+                    Some(FILL) => State::FillStart,
+                    Some(PING) => State::PingToken,
+                    Some(SELF_TEST) => State::SelfTestResult,
+                    Some(RESET) => {
+                        self.registers = [W::default(); REGISTER_FILE_SIZE];
+                        State::Idle
+                    }
+                    _ => State::Error,
+                };
+                W::default()
+            }
+            State::EchoPayload => self.echo_pending.replace(data).unwrap_or_default(),
+            State::WriteRegAddr => {
+                self.addr = data;
+                self.state = State::WriteRegValue;
+                if self.ack { W::from_u8(WRITE_REG) } else { W::default() }
+            }
+            State::WriteRegValue if self.crc => {
+                self.value = data;
+                self.state = State::WriteRegCrc;
+                W::default()
+            }
+            State::WriteRegValue => {
+                self.store(self.addr.low_byte(), data);
+                self.state = State::Idle;
+                W::default()
+            }
+            State::WriteRegCrc => {
+                // A bad CRC drops the write, like a real device would.
+                if data == W::crc8(&[W::from_u8(WRITE_REG), self.addr, self.value]) {
+                    self.store(self.addr.low_byte(), self.value);
+                }
+                self.state = State::Idle;
+                W::default()
+            }
+            State::ReadRegAddr => {
+                self.addr = data;
+                self.state = State::ReadRegValue;
+                self.read_header
+            }
+            State::ReadRegValue => {
+                self.reads += 1;
+                let addr = self.addr.low_byte();
+                self.value = self.registers[addr as usize];
+                if let Some(hook) = self.on_read {
+                    self.value = hook(addr, self.reads, self.value);
+                }
+                self.state = if !self.crc {
+                    State::Idle
+                } else if data == W::crc8(&[W::from_u8(READ_REG), self.addr]) {
+                    State::ReadRegCrc
+                } else {
+                    State::Error
+                };
+                self.value
+            }
+            State::ReadRegCrc => {
+                self.state = State::Idle;
+                let crc = W::crc8(&[self.value]);
+                if self.corrupt_crc {
+                    W::from_u8(crc.low_byte() ^ 0x01)
+                } else {
+                    crc
+                }
+            }
+            State::WhoAmIValue => {
+                self.state = State::Idle;
+                W::from_u8(WHO_AM_I_ID)
+            }
+            State::DumpAddr => {
+                self.addr = data;
+                self.state = State::DumpData;
+                W::default()
+            }
+            State::DumpData => {
+                let addr = self.addr.low_byte();
+                self.addr = W::from_u8(addr.wrapping_add(1));
+                self.registers[addr as usize]
+            }
+            State::FillStart => {
+                self.addr = data;
+                self.state = State::FillCount;
+                W::default()
+            }
+            State::FillCount => {
+                self.value = data;
+                self.state = State::FillValue;
+                W::default()
+            }
+            State::FillValue => {
+                let start = self.addr.low_byte();
+                for i in 0..self.value.low_byte() {
+                    self.store(start.wrapping_add(i), data);
+                }
+                self.state = State::Idle;
+                W::default()
+            }
+            State::PingToken => {
+                self.value = data;
+                self.state = State::PingReply;
+                W::default()
+            }
+            State::PingReply => {
+                self.state = State::Idle;
+                W::from_u8(self.value.low_byte() ^ PING_XOR)
+            }
+            State::SelfTestResult => {
+                self.state = State::Idle;
+                W::from_u8(if self.self_test() { SELF_TEST_PASS } else { 0x00 })
+            }
+            State::Error => W::from_u8(0xFF),
+        }
+    }
+
+    /// Equivalent of `MockSpiPeripheral.RunSelfTest()`: a register holds
+    /// what's written to it, and no echo word is left over.  Register 0 is
+    /// borrowed and put back.
+    fn self_test(&mut self) -> bool {
+        let saved = self.registers[0];
+        let mut ok = self.echo_pending.is_none();
+        for pattern in [0xA5, 0x5A] {
+            self.registers[0] = W::from_u8(pattern);
+            ok &= self.registers[0] == W::from_u8(pattern);
+        }
+        self.registers[0] = saved;
+
+        ok && !self.self_test_fails
+    }
+
+    /// Equivalent of `MockSpiPeripheral.FinishTransmission()` (CS deassert).
+    fn finish(&mut self) {
+        self.state = State::Idle;
+        self.echo_pending = None;
+    }
+}
+
+impl<W: Word> Default for ProtocolStub<W> {
+    fn default() -> Self {
+        Self {
+            registers: [W::default(); REGISTER_FILE_SIZE],
+            state: State::Idle,
+            echo_pending: None,
+            addr: W::default(),
+            value: W::default(),
+            crc: false,
+            corrupt_crc: false,
+            read_only: &[],
+            reads: 0,
+            on_read: None,
+            read_header: W::default(),
+            ack: false,
+            self_test_fails: false,
+            delays: Vec::new(),
+            transactions: 0,
+            log: Vec::new(),
+        }
+    }
+}
+
+impl<W> ErrorType for ProtocolStub<W> {
+    type Error = Infallible;
+}
+
+impl<W: Word> SpiDevice<W> for ProtocolStub<W> {
+    fn transaction(&mut self, operations: &mut [Operation<'_, W>]) -> Result<(), Infallible> {
+        self.transactions += 1;
+        for op in operations.iter_mut() {
+            let mut sent = Vec::new();
+            match op {
+                Operation::Write(buf) => {
+                    for &w in buf.iter() {
+                        sent.push(w);
+                        self.exchange(w);
+                    }
+                }
+                Operation::Read(buf) => {
+                    for slot in buf.iter_mut() {
+                        sent.push(W::default());
+                        *slot = self.exchange(W::default());
+                    }
+                }
+                Operation::Transfer(rx, tx) => {
+                    for i in 0..rx.len().max(tx.len()) {
+                        let w = tx.get(i).copied().unwrap_or_default();
+                        sent.push(w);
+                        let r = self.exchange(w);
+                        if let Some(slot) = rx.get_mut(i) {
+                            *slot = r;
+                        }
+                    }
+                }
+                Operation::TransferInPlace(buf) => {
+                    for slot in buf.iter_mut() {
+                        sent.push(*slot);
+                        *slot = self.exchange(*slot);
+                    }
+                }
+                Operation::DelayNs(ns) => {
+                    self.delays.push(*ns);
+                    continue;
+                }
+            }
+            self.log.push(sent);
+        }
+
+        self.finish();
+        Ok(())
+    }
+}
+
+/// Completes immediately – the blocking state machine never waits.
+#[cfg(feature = "async")]
+impl<W: Word> embedded_hal_async::spi::SpiDevice<W> for ProtocolStub<W> {
+    async fn transaction(&mut self, operations: &mut [Operation<'_, W>]) -> Result<(), Infallible> {
+        SpiDevice::transaction(self, operations)
+    }
+}
+
+/// Records every transmitted byte, across transactions, and answers with
+/// bytes queued by `with_rx` – zeros once they run out.
+///
+/// RX lines up with TX byte for byte, counting from the first byte of the
+/// first transaction.  To make `read_reg` (`[ReadReg, addr, 0]`) return
+/// `0xAB`, seed `with_rx(&[0x00, 0x00, 0xAB])`; the value is the third byte.
+#[derive(Default)]
+pub struct CaptureSpiDevice {
+    pub tx: Vec<u8>,
+    rx: VecDeque<u8>,
+}
+
+impl CaptureSpiDevice {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue `bytes` to be clocked back, after anything already queued.
+    pub fn with_rx(mut self, bytes: &[u8]) -> Self {
+        self.rx.extend(bytes);
+        self
+    }
+
+    fn exchange(&mut self, b: u8) -> u8 {
+        self.tx.push(b);
+        self.rx.pop_front().unwrap_or(0)
+    }
+}
+
+impl ErrorType for CaptureSpiDevice {
+    type Error = Infallible;
+}
+
+impl SpiDevice<u8> for CaptureSpiDevice {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Infallible> {
+        for op in operations.iter_mut() {
+            match op {
+                Operation::Write(buf) => {
+                    for &b in buf.iter() {
+                        self.exchange(b);
+                    }
+                }
+                Operation::Read(buf) => {
+                    for slot in buf.iter_mut() {
+                        *slot = self.exchange(0);
+                    }
+                }
+                Operation::Transfer(rx, tx) => {
+                    for i in 0..rx.len().max(tx.len()) {
+                        let r = self.exchange(tx.get(i).copied().unwrap_or(0));
+                        if let Some(slot) = rx.get_mut(i) {
+                            *slot = r;
+                        }
+                    }
+                }
+                Operation::TransferInPlace(buf) => {
+                    for slot in buf.iter_mut() {
+                        *slot = self.exchange(*slot);
+                    }
+                }
+                Operation::DelayNs(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The bus fault `FaultyStub` injects.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InjectedFault;
+
+impl embedded_hal::spi::Error for InjectedFault {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+/// A `ProtocolStub` whose transactions fail on a schedule.  A failed
+/// transaction never reaches the inner stub.
+#[derive(Default)]
+pub struct FaultyStub {
+    pub inner: ProtocolStub,
+    /// Transactions attempted so far, failed ones included.
+    pub calls: u32,
+    fail_first: u32,
+    fail_after: Option<u32>,
+    fail_on: Option<u8>,
+}
+
+impl FaultyStub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fail the first `n` transactions, then let them through – a
+    /// transient fault for retries to ride out.
+    pub fn fail_first(mut self, n: u32) -> Self {
+        self.fail_first = n;
+        self
+    }
+
+    /// Let `n` transactions through, then fail every one after.
+    pub fn fail_after(mut self, n: u32) -> Self {
+        self.fail_after = Some(n);
+        self
+    }
+
+    /// Fail every transaction whose first word is `opcode`.
+    pub fn fail_on(mut self, opcode: u8) -> Self {
+        self.fail_on = Some(opcode);
+        self
+    }
+
+    fn opcode(operations: &[Operation<'_, u8>]) -> Option<u8> {
+        operations.iter().find_map(|op| match op {
+            Operation::Write(buf) => buf.first().copied(),
+            Operation::Transfer(_, tx) => tx.first().copied(),
+            Operation::TransferInPlace(buf) => buf.first().copied(),
+            Operation::Read(_) | Operation::DelayNs(_) => None,
+        })
+    }
+}
+
+impl ErrorType for FaultyStub {
+    type Error = InjectedFault;
+}
+
+impl SpiDevice<u8> for FaultyStub {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), InjectedFault> {
+        self.calls += 1;
+        let early = self.calls <= self.fail_first;
+        let exhausted = self.fail_after.is_some_and(|n| self.calls > n);
+        let targeted = self.fail_on.is_some() && Self::opcode(operations) == self.fail_on;
+        if early || exhausted || targeted {
+            return Err(InjectedFault);
+        }
+
+        let Ok(()) = self.inner.transaction(operations);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn echo_answers_one_byte_behind() {
+        let mut stub = ProtocolStub::new();
+        let mut wire = [ECHO, 0x11, 0x22, 0x00];
+
+        stub.transfer_in_place(&mut wire).unwrap();
+
+        assert_eq!(wire, [0x00, 0x00, 0x11, 0x22]);
+    }
+
+    #[test]
+    fn unknown_opcode_answers_ff_until_cs_deasserts() {
+        let mut stub = ProtocolStub::new();
+        let mut wire = [0x7E, 0x00, 0x00];
+
+        stub.transfer_in_place(&mut wire).unwrap();
+        assert_eq!(wire, [0x00, 0xFF, 0xFF]);
+
+        // The next transaction starts from Idle again.
+        let mut wire = [WHO_AM_I, 0x00];
+        stub.transfer_in_place(&mut wire).unwrap();
+        assert_eq!(wire[1], WHO_AM_I_ID);
+    }
+
+    #[test]
+    fn write_reg_only_lands_once_the_value_word_arrives() {
+        let mut stub = ProtocolStub::new();
+
+        stub.write(&[WRITE_REG, 0x04]).unwrap();
+        assert_eq!(stub.registers[0x04], 0x00);
+
+        stub.write(&[WRITE_REG, 0x04, 0x99]).unwrap();
+        assert_eq!(stub.registers[0x04], 0x99);
+    }
+}
@@ -1,65 +1,1998 @@
+#![allow(dead_code)]
+
+use core::marker::PhantomData;
+
+use embedded_hal::delay::DelayNs;
 use embedded_hal::spi::{SpiDevice, Operation};
 
+use crate::output::Output;
+use crate::report::hex_grid;
+
 #[repr(u8)]
-enum Command {
+#[derive(Debug, Copy, Clone)]
+pub(crate) enum Command {
     Echo = 1,
     WriteReg = 2,
     ReadReg = 3,
+    WhoAmI = 4,
+    Reset = 5,
+    Dump = 6,
+    Fill = 7,
+    Ping = 8,
+    SelfTest = 9,
+}
+
+/// The opcode byte `MockSpiDriver` sends for each command.  `DEFAULT` is
+/// the `Command` numbering the bundled C# mock uses; override fields for a
+/// device or mock that numbers them differently.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Opcodes {
+    pub echo: u8,
+    pub write_reg: u8,
+    pub read_reg: u8,
+    pub who_am_i: u8,
+    pub reset: u8,
+    pub dump: u8,
+    pub fill: u8,
+    pub ping: u8,
+    pub self_test: u8,
+}
+
+impl Opcodes {
+    pub const DEFAULT: Self = Self {
+        echo: Command::Echo as u8,
+        write_reg: Command::WriteReg as u8,
+        read_reg: Command::ReadReg as u8,
+        who_am_i: Command::WhoAmI as u8,
+        reset: Command::Reset as u8,
+        dump: Command::Dump as u8,
+        fill: Command::Fill as u8,
+        ping: Command::Ping as u8,
+        self_test: Command::SelfTest as u8,
+    };
+}
+
+impl Default for Opcodes {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
 }
 
+/// Identity byte returned by `Command::WhoAmI`.  Must match `WhoAmIValue`
+/// in `MockSpiPeripheral.cs`.
+pub const WHO_AM_I_ID: u8 = 0x4D;
+
+/// `Command::Ping` answers with its token XORed with this, so a bare
+/// MISO–MOSI loopback can't pass for the mock.  Must match `PingXor` in
+/// `MockSpiPeripheral.cs`.
+pub const PING_XOR: u8 = 0xA5;
+
+/// `Command::SelfTest`'s status byte when the mock's own checks pass; any
+/// other value is a failure.  Must match `SelfTestPass` in
+/// `MockSpiPeripheral.cs`.
+pub const SELF_TEST_PASS: u8 = 0x5A;
+
+// `MockError` is the name callers match on, even though it repeats the
+// enum's.
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum Error {
+    /// The underlying `SpiDevice` reported a bus fault.
     Spi,
+    /// The device didn't become ready in time.
+    Timeout,
+    /// The device answered, but not in the shape the protocol expects.
+    Protocol,
+    /// The payload doesn't fit in a single frame.
+    PayloadTooLong,
+    /// A caller-supplied buffer has the wrong length: a scratch buffer
+    /// too short for the frame, or `raw` slices that differ.
+    Length,
+    /// The CRC trailing a read response didn't match its data.
+    CrcMismatch,
+    /// The device rejected the opcode (the mock answers 0xFF to commands it
+    /// doesn't know).
+    UnknownCommand(u8),
+    /// Register readback after a write didn't match the written value.
+    Verify { expected: u8, got: u8 },
+    /// Every word of a read came back 0xFF (or 0x00): MISO is likely stuck
+    /// or CS never reached the device.  Only with `with_disconnect_check`.
+    LikelyDisconnected,
+    /// `write_reg_ack` got this back instead of its opcode: the device
+    /// didn't see the command it was sent.
+    Nack(u8),
+    /// The device answered a read with the `with_error_sentinel` byte; this
+    /// is the reason code that followed it.
+    MockError(u8),
+}
+
+impl Error {
+    /// Short, allocation-free description, for printing without `core::fmt`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Error::Spi => "SPI bus error",
+            Error::Timeout => "timed out",
+            Error::Protocol => "protocol error",
+            Error::PayloadTooLong => "payload too long",
+            Error::Length => "wrong buffer length",
+            Error::CrcMismatch => "CRC mismatch",
+            Error::UnknownCommand(_) => "unknown command",
+            Error::Verify { .. } => "readback mismatch",
+            Error::LikelyDisconnected => "all 0xFF/0x00 read back - check CS/MISO wiring",
+            Error::Nack(_) => "write not acknowledged",
+            Error::MockError(_) => "mock reported an error",
+        }
+    }
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::UnknownCommand(op) => write!(f, "{} 0x{:02X}", self.as_str(), op),
+            Error::Nack(got) => write!(f, "{}, got 0x{:02X}", self.as_str(), got),
+            Error::MockError(code) => write!(f, "{}: code 0x{:02X}", self.as_str(), code),
+            Error::Verify { expected, got } => {
+                write!(f, "{}: expected 0x{:02X}, got 0x{:02X}", self.as_str(), expected, got)
+            }
+            _ => f.write_str(self.as_str()),
+        }
+    }
+}
+
+/// CRC-8/SMBUS: poly 0x07, init 0x00, no reflection, no final XOR.
+pub(crate) fn crc8(data: &[u8]) -> u8 {
+    crc8_update(0, data)
+}
+
+fn crc8_update(mut crc: u8, data: &[u8]) -> u8 {
+    for &b in data {
+        crc ^= b;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// SPI frame word.  Every protocol field – opcode, address, value, CRC –
+/// occupies one whole word, so on a `u16` bus `WriteReg` is
+/// `[0x0002, addr, value]`.
+pub trait Word: Copy + Default + PartialEq + 'static {
+    /// Widen an opcode (or any byte) into a word.
+    fn from_u8(b: u8) -> Self;
+
+    /// The low byte, e.g. to index a 256-entry register file.
+    fn low_byte(self) -> u8;
+
+    /// CRC-8 over the big-endian bytes of `words`, widened to a word.
+    fn crc8(words: &[Self]) -> Self;
+
+    /// The word with the bits of `mask` set, e.g. a direction bit.
+    fn set_bits(self, mask: u8) -> Self;
+
+    /// Every bit set – what a floating, pulled-up MISO reads as.
+    const ONES: Self;
+}
+
+/// `words` are all 0x00 or all ones: a bus with nothing driving MISO.
+fn stuck<W: Word>(words: &[W]) -> bool {
+    !words.is_empty() && (words.iter().all(|&w| w == W::ONES) || words.iter().all(|&w| w == W::default()))
+}
+
+impl Word for u8 {
+    const ONES: Self = 0xFF;
+
+    fn from_u8(b: u8) -> Self {
+        b
+    }
+
+    fn low_byte(self) -> u8 {
+        self
+    }
+
+    fn crc8(words: &[Self]) -> Self {
+        crc8(words)
+    }
+
+    fn set_bits(self, mask: u8) -> Self {
+        self | mask
+    }
+}
+
+impl Word for u16 {
+    const ONES: Self = 0xFFFF;
+
+    fn from_u8(b: u8) -> Self {
+        b.into()
+    }
+
+    fn low_byte(self) -> u8 {
+        self as u8
+    }
+
+    fn crc8(words: &[Self]) -> Self {
+        words
+            .iter()
+            .fold(0, |crc, w| crc8_update(crc, &w.to_be_bytes()))
+            .into()
+    }
+
+    fn set_bits(self, mask: u8) -> Self {
+        self | u16::from(mask)
+    }
+}
+
+/// How `read_reg` / `write_reg` tell the device what they want.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Addressing {
+    /// `[opcode, addr, ..]` – the mock's protocol, and the default.
+    Command,
+    /// No opcode: a read sends `[addr | bit, 0]` and gets the value back in
+    /// the second word, a write sends `[addr, value]`.  The usual sensor
+    /// convention, with the read bit (often bit 7) given here.  CRC is
+    /// a `Command` feature and isn't sent in this mode.
+    DirectionBit(u8),
+}
+
+/// Byte order of a multi-byte register spread over consecutive 8-bit
+/// registers: `Big` puts the most significant byte at the lowest address.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Endian {
+    #[default]
+    Big,
+    Little,
+}
+
+impl Endian {
+    /// Big-endian bytes `be` in this order, lowest address first – or, as
+    /// reversing undoes itself, the register bytes back in big-endian.
+    fn order<const N: usize>(self, mut be: [u8; N]) -> [u8; N] {
+        if self == Endian::Little {
+            be.reverse();
+        }
+        be
+    }
+}
+
+/// A typed device register, for `MockSpiDriver::read` / `write` instead
+/// of raw addresses and bytes.  `ADDR` pins the address at compile time;
+/// `from_bits` / `to_bits` convert to and from the register byte.
+pub trait Register {
+    const ADDR: u8;
+
+    fn from_bits(bits: u8) -> Self;
+    fn to_bits(self) -> u8;
+}
+
+// ---------------------------------------------------------------------------
+// Frame layout – shared by the blocking and async drivers
+// ---------------------------------------------------------------------------
+
+/// Default echo scratch size: opcode + up to 255 payload words + the lag
+/// word.  `MockSpiDriver`'s `BUF` overrides it.
+pub const ECHO_WIRE_LEN: usize = 257;
+
+/// Largest payload one echo frame carries: the mock's length is a byte.
+pub const ECHO_CHUNK: usize = 255;
+
+/// Words an echo frame adds around its payload: the opcode and the lag word.
+pub(crate) const ECHO_OVERHEAD: usize = 2;
+
+/// `[op, payload.., 0]` in a `BUF`-word scratch buffer, and how many
+/// words of it to clock.  The mock answers one word behind, so the payload
+/// comes back at `wire[2..len]`.
+pub(crate) fn echo_frame<W: Word, const BUF: usize>(
+    op: u8,
+    buf: &[W],
+) -> Result<([W; BUF], usize), Error> {
+    if buf.len() + ECHO_OVERHEAD > BUF {
+        return Err(Error::PayloadTooLong);
+    }
+
+    let mut wire = [W::default(); BUF];
+    let len = write_echo_frame(op, buf, &mut wire)?;
+
+    Ok((wire, len))
+}
+
+/// Lay `[op, payload.., 0]` out at the start of `wire`, returning its
+/// length; `Error::Length` if `wire` can't hold it.
+pub(crate) fn write_echo_frame<W: Word>(op: u8, buf: &[W], wire: &mut [W]) -> Result<usize, Error> {
+    let len = buf.len() + ECHO_OVERHEAD;
+    if len > wire.len() {
+        return Err(Error::Length);
+    }
+
+    wire[0] = W::from_u8(op);
+    wire[1..=buf.len()].copy_from_slice(buf);
+    wire[len - 1] = W::default();
+
+    Ok(len)
+}
+
+/// `[op, addr, value]`, plus a CRC word when `crc` is set.
+pub(crate) fn write_reg_frame<W: Word>(op: u8, addr: W, value: W, crc: bool) -> ([W; 4], usize) {
+    let mut frame = [W::from_u8(op), addr, value, W::default()];
+    if crc {
+        frame[3] = W::crc8(&frame[..3]);
+        (frame, 4)
+    } else {
+        (frame, 3)
+    }
+}
+
+/// Most `(addr, value)` pairs `write_reg_batch` sends under one CS.
+pub const BATCH_MAX: usize = 32;
+
+/// `[op, addr, 0]`, or `[op, addr, crc, 0]` when `crc` is set.
+pub(crate) fn read_reg_frame<W: Word>(op: u8, addr: W, crc: bool) -> ([W; 4], usize) {
+    let mut tx = [W::from_u8(op), addr, W::default(), W::default()];
+    if crc {
+        tx[2] = W::crc8(&tx[..2]);
+        (tx, 4)
+    } else {
+        (tx, 3)
+    }
+}
+
+/// The value from a `read_reg_frame` response, checking its CRC if enabled.
+pub(crate) fn read_reg_response<W: Word>(rx: &[W; 4], crc: bool) -> Result<W, Error> {
+    if crc && rx[3] != W::crc8(&rx[2..3]) {
+        return Err(Error::CrcMismatch);
+    }
+
+    Ok(rx[2])
+}
+
+/// Words `op` clocks: a `Transfer` runs for its longer slice.
+fn clocked<W>(op: &Operation<'_, W>) -> usize {
+    match op {
+        Operation::Write(buf) => buf.len(),
+        Operation::Read(buf) => buf.len(),
+        Operation::Transfer(rx, tx) => rx.len().max(tx.len()),
+        Operation::TransferInPlace(buf) => buf.len(),
+        Operation::DelayNs(_) => 0,
+    }
+}
+
+/// Address bit `read_regs`/`write_regs` set for auto-increment unless
+/// `with_increment_mask` says otherwise.
+pub const DEFAULT_INCREMENT_MASK: u8 = 1 << 7;
+
+/// `DelayNs` that returns at once: `MockSpiDriver`'s default, so a driver
+/// without `with_delay` carries no delay state and never waits.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct NoDelay;
+
+impl DelayNs for NoDelay {
+    fn delay_ns(&mut self, _ns: u32) {}
 }
 
-pub struct MockSpiDriver<SPI> {
+/// Driver for the mock peripheral over any `SpiDevice<W>`.
+///
+/// `echo`, `write_reg` and `read_reg` work for every `Word`; the remaining
+/// helpers are byte-oriented and only exist for `W = u8`.
+///
+/// `BUF` sizes `echo`'s stack scratch buffer, capping payloads at
+/// `BUF - 2` words.  `new` uses `ECHO_WIRE_LEN`; `with_echo_buf` trades
+/// stack for payload on small targets.
+///
+/// `D` times the gap `with_delay` puts between commands.
+pub struct MockSpiDriver<SPI, W = u8, const BUF: usize = ECHO_WIRE_LEN, D = NoDelay> {
     spi: SPI,
+    opcodes: Opcodes,
+    crc: bool,
+    settle_ns: u32,
+    increment_mask: u8,
+    read_mask: u8,
+    addressing: Addressing,
+    retries: u32,
+    retry_writes: bool,
+    disconnect_check: bool,
+    error_sentinel: Option<u8>,
+    endian: Endian,
+    delay: D,
+    inter_command_ns: u32,
+    last_len: usize,
+    _word: PhantomData<W>,
 }
 
-impl<SPI: SpiDevice> MockSpiDriver<SPI> {
+impl<SPI: SpiDevice<W>, W: Word> MockSpiDriver<SPI, W> {
     pub fn new(spi: SPI) -> Self {
-        Self { spi }
+        Self::with_opcodes(spi, Opcodes::DEFAULT)
+    }
+
+    /// A driver that sends `opcodes` instead of the mock's numbering.
+    pub fn with_opcodes(spi: SPI, opcodes: Opcodes) -> Self {
+        Self {
+            spi,
+            opcodes,
+            crc: false,
+            settle_ns: 0,
+            increment_mask: DEFAULT_INCREMENT_MASK,
+            read_mask: 0,
+            addressing: Addressing::Command,
+            retries: 0,
+            retry_writes: false,
+            disconnect_check: false,
+            error_sentinel: None,
+            endian: Endian::Big,
+            delay: NoDelay,
+            inter_command_ns: 0,
+            last_len: 0,
+            _word: PhantomData,
+        }
+    }
+}
+
+impl<'a, SPI: SpiDevice<W>, W: Word> MockSpiDriver<&'a mut SPI, W> {
+    /// A driver that borrows `spi` rather than owning it, so several can
+    /// take turns on one bus; drop it to get the bus back.  Same as
+    /// `new(&mut spi)` – `embedded-hal` implements `SpiDevice` for `&mut`.
+    pub fn new_ref(spi: &'a mut SPI) -> Self {
+        Self::new(spi)
+    }
+}
+
+impl<SPI: SpiDevice<W>, W: Word, const BUF: usize, D: DelayNs> MockSpiDriver<SPI, W, BUF, D> {
+    /// The same driver with a `N`-word echo scratch buffer.
+    pub fn with_echo_buf<const N: usize>(self) -> MockSpiDriver<SPI, W, N, D> {
+        MockSpiDriver {
+            spi: self.spi,
+            opcodes: self.opcodes,
+            crc: self.crc,
+            settle_ns: self.settle_ns,
+            increment_mask: self.increment_mask,
+            read_mask: self.read_mask,
+            addressing: self.addressing,
+            retries: self.retries,
+            retry_writes: self.retry_writes,
+            disconnect_check: self.disconnect_check,
+            error_sentinel: self.error_sentinel,
+            endian: self.endian,
+            delay: self.delay,
+            inter_command_ns: self.inter_command_ns,
+            last_len: self.last_len,
+            _word: PhantomData,
+        }
+    }
+
+    /// Wait `inter_command_ns` on `delay` after every command, for devices
+    /// that need settling time between, say, a write and the next read.
+    /// The wait runs with CS deasserted, after the command's transaction –
+    /// for a pause inside a read see `with_settle_ns`.  0 never waits.
+    pub fn with_delay<D2: DelayNs>(self, delay: D2, inter_command_ns: u32) -> MockSpiDriver<SPI, W, BUF, D2> {
+        MockSpiDriver {
+            spi: self.spi,
+            opcodes: self.opcodes,
+            crc: self.crc,
+            settle_ns: self.settle_ns,
+            increment_mask: self.increment_mask,
+            read_mask: self.read_mask,
+            addressing: self.addressing,
+            retries: self.retries,
+            retry_writes: self.retry_writes,
+            disconnect_check: self.disconnect_check,
+            error_sentinel: self.error_sentinel,
+            endian: self.endian,
+            delay,
+            inter_command_ns,
+            last_len: self.last_len,
+            _word: PhantomData,
+        }
+    }
+
+    /// Pause `ns` nanoseconds between a read's opcode/address and its data
+    /// phase (`Operation::DelayNs`), for devices that need time to fetch
+    /// the value.  0, the default, keeps reads a single transfer.
+    pub fn with_settle_ns(mut self, ns: u32) -> Self {
+        self.settle_ns = ns;
+        self
+    }
+
+    /// The address bit `read_regs`/`write_regs` set to ask for
+    /// auto-increment.  Bit 7 (`DEFAULT_INCREMENT_MASK`) by default; some
+    /// parts use bit 6, and 0 suits devices that always increment.
+    pub fn with_increment_mask(mut self, mask: u8) -> Self {
+        self.increment_mask = mask;
+        self
+    }
+
+    /// The address bit that marks a burst as a read, for parts that carry
+    /// the direction in the address.  0, the default, leaves reads and
+    /// writes addressed alike.
+    pub fn with_read_mask(mut self, mask: u8) -> Self {
+        self.read_mask = mask;
+        self
+    }
+
+    /// Byte order of the 16- and 32-bit register accessors.  `Endian::Big`
+    /// by default.
+    pub fn with_endian(mut self, endian: Endian) -> Self {
+        self.endian = endian;
+        self
+    }
+
+    /// Switch `read_reg` / `write_reg` between the mock's opcodes and a
+    /// direction bit in the address byte.  `Addressing::Command` by default.
+    pub fn with_addressing(mut self, addressing: Addressing) -> Self {
+        self.addressing = addressing;
+        self
+    }
+
+    /// Retry a command up to `n` more times when the bus reports an error
+    /// (`Error::Spi`) or a poll runs out (`Error::Timeout`), for flaky links
+    /// or fault injection.  0, the default, never retries.
+    ///
+    /// Only commands that are safe to repeat retry: register reads,
+    /// `dump`, `read_regs`, `who_am_i`, `ping` and `reset`.  Writes
+    /// (`write_reg`, `write_regs`, `fill`) don't, because a failed write
+    /// may still have landed and a device with side effects on write – a
+    /// FIFO, clear-on-write bits – would see it twice; opt them in with
+    /// `with_write_retries`.  `echo`, `command_then_read` and `raw` never
+    /// retry.
+    pub fn with_retries(mut self, n: u32) -> Self {
+        self.retries = n;
+        self
+    }
+
+    /// Let writes retry too (see `with_retries`), for registers that don't
+    /// mind being written twice.
+    pub fn with_write_retries(mut self, enabled: bool) -> Self {
+        self.retry_writes = enabled;
+        self
+    }
+
+    /// Fail `read_reg` and `read_regs` with `Error::LikelyDisconnected`
+    /// when every word clocked back is 0xFF or 0x00, instead of returning
+    /// it as data – turns "wrong value" into "check the wiring".
+    ///
+    /// Off by default: it can't tell a dead bus from a device that really
+    /// answers that.  The bundled mock clocks 0x00 under the opcode and
+    /// address, so a register holding 0x00 trips it too; enable it for
+    /// reads that are never all zero, such as an ID or a status register.
+    pub fn with_disconnect_check(mut self) -> Self {
+        self.disconnect_check = true;
+        self
+    }
+
+    /// Treat `sentinel` in a read's status position – the first word
+    /// clocked back after the opcode (or, with no opcode, the address) – as
+    /// the device flagging an error.  Every reading command (`read_reg`,
+    /// `dump`, `read_regs`, `command_then_read`, `who_am_i`, `ping`,
+    /// `self_test`) then fails with `Error::MockError` carrying the word
+    /// after it as the reason code, or 0 if the command clocks none.
+    ///
+    /// Off by default.  Where the status position holds data – `read_regs`,
+    /// `command_then_read`, `Addressing::DirectionBit` reads – a register
+    /// that really holds `sentinel` reads as an error too.
+    pub fn with_error_sentinel(mut self, sentinel: u8) -> Self {
+        self.error_sentinel = Some(sentinel);
+        self
+    }
+
+    /// `Error::MockError` if `reply` – the words clocked back from the
+    /// status position on – starts with the configured sentinel.
+    fn check_reply(&self, reply: &[W]) -> Result<(), Error> {
+        match (self.error_sentinel, reply) {
+            (Some(s), [status, rest @ ..]) if *status == W::from_u8(s) => {
+                Err(Error::MockError(rest.first().map_or(0, |r| r.low_byte())))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Protect register commands with a CRC-8.
+    ///
+    /// `write_reg` appends a CRC over `[opcode, addr, value]`.  `read_reg`
+    /// sends a CRC over `[opcode, addr]` in the turnaround byte and checks
+    /// the CRC the device returns after the value; `read_regs` checks one
+    /// after its data.  Off by default, because
+    /// the bundled C# mock doesn't speak CRC.
+    pub fn with_crc(mut self, enabled: bool) -> Self {
+        self.crc = enabled;
+        self
     }
 
     pub fn into_inner(self) -> SPI {
         self.spi
     }
 
-    pub fn echo(&mut self, buf: &mut [u8]) -> Result<(), Error> {
-        if buf.len() == 0 {
+    /// Words the last command clocked, command framing included – for
+    /// throughput figures.  A command built from several transactions
+    /// (`write_reg_verify`, the polling waits) leaves the last one's count;
+    /// `echo_streaming` and `write_stream` leave their total.  A command
+    /// the bus fails counts what it tried to clock; one rejected before
+    /// reaching the bus leaves the count alone.
+    pub fn last_transfer_len(&self) -> usize {
+        self.last_len
+    }
+
+    pub fn spi(&self) -> &SPI {
+        &self.spi
+    }
+
+    /// Borrow the bus for raw `transaction`s the driver doesn't model,
+    /// e.g. a vendor command, without giving up the driver.
+    pub fn spi_mut(&mut self) -> &mut SPI {
+        &mut self.spi
+    }
+
+    /// Clock `tx` out and `rx` in as one `Operation::Transfer`, for ad-hoc
+    /// commands not worth a method.  The slices must be the same length,
+    /// else `Error::Length`; see `raw_padded` for frames that aren't.
+    pub fn raw(&mut self, tx: &[W], rx: &mut [W]) -> Result<(), Error> {
+        if tx.len() != rx.len() {
+            return Err(Error::Length);
+        }
+        self.raw_padded(tx, rx)
+    }
+
+    /// `raw` for slices of different lengths, padded the `Transfer` way:
+    /// words past the end of `tx` go out as the bus's filler, and words
+    /// clocked in past the end of `rx` are dropped.
+    pub fn raw_padded(&mut self, tx: &[W], rx: &mut [W]) -> Result<(), Error> {
+        self.last_len = rx.len().max(tx.len());
+        let result = self.spi.transaction(&mut [Operation::Transfer(rx, tx)]);
+        self.gap();
+        result.map_err(|_| Error::Spi)
+    }
+
+    /// Echo `buf` through the driver's `BUF`-word stack scratch; payloads
+    /// past `BUF - 2` words are `Error::PayloadTooLong`.  See `echo_with`
+    /// for larger blocks.
+    pub fn echo(&mut self, buf: &mut [W]) -> Result<(), Error> {
+        if buf.is_empty() {
             return Ok(());
         }
 
-        let len = buf.len();
+        let (mut wire, len) = echo_frame::<W, BUF>(self.opcodes.echo, buf)?;
+        self.echo_wire(buf, &mut wire[..len])
+    }
 
-        let mut wire = [0u8; 257];
-        wire[0] = Command::Echo as u8;
-        wire[1..=len].copy_from_slice(buf);
+    /// `echo` through a caller-supplied `scratch` of at least
+    /// `buf.len() + 2` words, so the payload is bounded by the caller's RAM
+    /// rather than `BUF`.  A shorter `scratch` is `Error::Length`.
+    pub fn echo_with(&mut self, buf: &mut [W], scratch: &mut [W]) -> Result<(), Error> {
+        if buf.is_empty() {
+            return Ok(());
+        }
 
-        self.spi
-            .transfer_in_place(&mut wire[..len + 2])
-            .map_err(|_| Error::Spi)?;
+        let len = write_echo_frame(self.opcodes.echo, buf, scratch)?;
+        self.echo_wire(buf, &mut scratch[..len])
+    }
 
-        buf.copy_from_slice(&wire[2..len + 2]);
+    /// Echo a `buf` of any length as a run of `Echo` commands of at most
+    /// `ECHO_CHUNK` words each (fewer if `BUF` is smaller).  Every chunk is
+    /// its own frame with its own chip select, so the mock must not carry
+    /// state between them; an error stops the run with the chunks before
+    /// it already echoed.
+    pub fn echo_streaming(&mut self, buf: &mut [W]) -> Result<(), Error> {
+        let chunk = ECHO_CHUNK.min(BUF.saturating_sub(ECHO_OVERHEAD)).max(1);
+        let mut total = 0;
+        for part in buf.chunks_mut(chunk) {
+            let result = self.echo(part);
+            total += self.last_len;
+            self.last_len = total;
+            result?;
+        }
 
         Ok(())
     }
 
-    pub fn write_reg(&mut self, addr: u8, value: u8) -> Result<(), Error> {
-        self.spi
-            .transaction(&mut [Operation::Write(&[Command::WriteReg as u8, addr, value])])
-            .map_err(|_| Error::Spi)
+    /// Clock a laid-out echo frame and copy the returned payload into `buf`.
+    fn echo_wire(&mut self, buf: &mut [W], wire: &mut [W]) -> Result<(), Error> {
+        self.last_len = wire.len();
+        let result = self.spi.transfer_in_place(wire);
+        self.gap();
+        result.map_err(|_| Error::Spi)?;
+
+        buf.copy_from_slice(&wire[2..]);
+
+        Ok(())
     }
 
-    pub fn read_reg(&mut self, addr: u8) -> Result<u8, Error> {
-        let mut rx = [0u8; 3];
+    pub fn write_reg(&mut self, addr: W, value: W) -> Result<(), Error> {
+        let (frame, len) = match self.addressing {
+            Addressing::Command => write_reg_frame(self.opcodes.write_reg, addr, value, self.crc),
+            Addressing::DirectionBit(_) => ([addr, value, W::default(), W::default()], 2),
+        };
 
-        self.spi
-            .transaction(&mut [Operation::Transfer(&mut rx, &[Command::ReadReg as u8, addr, 0x0])])
-            .map_err(|_| Error::Spi)?;
+        self.transact(false, &mut [Operation::Write(&frame[..len])])
+    }
 
-        Ok(rx[2])
+    /// Write each `(addr, value)` of `pairs` in order, all under one CS
+    /// assertion instead of one per register.
+    ///
+    /// Framing: the `write_reg` frames back to back in a single
+    /// `Operation::Write` – `[WriteReg, a0, v0, WriteReg, a1, v1, ..]`,
+    /// each with its CRC word under `with_crc`, or bare `[a0, v0, a1, v1,
+    /// ..]` with `Addressing::DirectionBit`.  The mock drops back to idle
+    /// after every write, so the next word is read as a fresh opcode.  Up
+    /// to `BATCH_MAX` pairs, else `Error::PayloadTooLong`.
+    pub fn write_reg_batch(&mut self, pairs: &[(W, W)]) -> Result<(), Error> {
+        if pairs.len() > BATCH_MAX {
+            return Err(Error::PayloadTooLong);
+        }
+
+        let mut wire = [W::default(); BATCH_MAX * 4];
+        let mut len = 0;
+        for &(addr, value) in pairs {
+            let (frame, n) = match self.addressing {
+                Addressing::Command => write_reg_frame(self.opcodes.write_reg, addr, value, self.crc),
+                Addressing::DirectionBit(_) => ([addr, value, W::default(), W::default()], 2),
+            };
+            wire[len..len + n].copy_from_slice(&frame[..n]);
+            len += n;
+        }
+        if len == 0 {
+            return Ok(());
+        }
+
+        self.transact(false, &mut [Operation::Write(&wire[..len])])
+    }
+
+    /// `write_reg` for a device that acks by clocking the opcode back
+    /// under the next word (the address, as replies lag a word).  Anything
+    /// else there is `Error::Nack`, e.g. when a corrupted opcode made the
+    /// device drop the write.  Needs `Addressing::Command` – with no opcode
+    /// on the wire there's nothing to ack, so it's `Error::Protocol`.
+    pub fn write_reg_ack(&mut self, addr: W, value: W) -> Result<(), Error> {
+        if self.addressing != Addressing::Command {
+            return Err(Error::Protocol);
+        }
+
+        let op = self.opcodes.write_reg;
+        let (frame, len) = write_reg_frame(op, addr, value, self.crc);
+        let mut rx = [W::default(); 4];
+        self.transact(false, &mut [Operation::Transfer(&mut rx[..len], &frame[..len])])?;
+
+        if rx[1] != W::from_u8(op) {
+            return Err(Error::Nack(rx[1].low_byte()));
+        }
+        Ok(())
+    }
+
+    pub fn read_reg(&mut self, addr: W) -> Result<W, Error> {
+        let rx = self.read_reg_raw(addr)?;
+        self.read_reg_value(&rx)
+    }
+
+    /// The value in a `read_reg` frame's `rx`, after the checks `read_reg`
+    /// applies: the disconnect check, the error sentinel and the CRC.
+    fn read_reg_value(&self, rx: &[W; 4]) -> Result<W, Error> {
+        let len = match self.addressing {
+            Addressing::Command if self.crc => 4,
+            Addressing::Command => 3,
+            Addressing::DirectionBit(_) => 2,
+        };
+        if self.disconnect_check && stuck(&rx[..len]) {
+            return Err(Error::LikelyDisconnected);
+        }
+        self.check_reply(&rx[1..len])?;
+
+        match self.addressing {
+            Addressing::Command => read_reg_response(rx, self.crc),
+            Addressing::DirectionBit(_) => Ok(rx[1]),
+        }
+    }
+
+    /// Everything clocked back during a `read_reg`, unchecked – for
+    /// bringing up a mock whose framing doesn't match yet, or for devices
+    /// that report status in the header words `[0..2]`.  The value is
+    /// documented to land at `[2]`, its CRC (if enabled) at `[3]`; words
+    /// past the frame (3 long, 4 with CRC) are zero.  With
+    /// `Addressing::DirectionBit` the frame is 2 long and the value at `[1]`.
+    pub fn read_reg_raw(&mut self, addr: W) -> Result<[W; 4], Error> {
+        let (tx, len, at) = self.read_reg_tx(addr);
+        let mut rx = [W::default(); 4];
+
+        if self.settle_ns == 0 {
+            self.transact(true, &mut [Operation::Transfer(&mut rx[..len], &tx[..len])])?;
+        } else {
+            let settle_ns = self.settle_ns;
+            // The header is a Transfer too, so the status word under the
+            // address is still captured.
+            let (header, data) = rx[..len].split_at_mut(at);
+            self.transact(true, &mut [
+                Operation::Transfer(header, &tx[..at]),
+                Operation::DelayNs(settle_ns),
+                Operation::Transfer(data, &tx[at..len]),
+            ])?;
+        }
+
+        Ok(rx)
+    }
+
+    /// The `read_reg` frame for `addr` in the configured addressing mode:
+    /// its words, how many to send, and `at`, where the value comes back –
+    /// nothing before it is data.
+    fn read_reg_tx(&self, addr: W) -> ([W; 4], usize, usize) {
+        match self.addressing {
+            Addressing::Command => {
+                let (tx, len) = read_reg_frame(self.opcodes.read_reg, addr, self.crc);
+                (tx, len, 2)
+            }
+            Addressing::DirectionBit(read) => {
+                ([addr.set_bits(read), W::default(), W::default(), W::default()], 2, 1)
+            }
+        }
+    }
+
+    /// Run `operations`, retrying bus errors as `with_retries` allows.
+    /// `idempotent` commands always may; writes only with
+    /// `with_write_retries`.
+    fn transact(&mut self, idempotent: bool, operations: &mut [Operation<'_, W>]) -> Result<(), Error> {
+        let retries = if idempotent || self.retry_writes { self.retries } else { 0 };
+        self.last_len = operations.iter().map(clocked).sum();
+        let mut result = self.spi.transaction(operations);
+        for _ in 0..retries {
+            if result.is_ok() {
+                break;
+            }
+            result = self.spi.transaction(operations);
+        }
+        self.gap();
+        result.map_err(|_| Error::Spi)
+    }
+
+    /// The `with_delay` pause that follows every command.
+    fn gap(&mut self) {
+        if self.inter_command_ns != 0 {
+            self.delay.delay_ns(self.inter_command_ns);
+        }
     }
 }
 
+impl<SPI: SpiDevice<u8>, const BUF: usize, D: DelayNs> MockSpiDriver<SPI, u8, BUF, D> {
+
+    /// `write_reg` followed by `read_reg`, failing if the value didn't stick.
+    pub fn write_reg_verify(&mut self, addr: u8, value: u8) -> Result<(), Error> {
+        self.write_reg(addr, value)?;
+
+        let got = self.read_reg(addr)?;
+        if got != value {
+            return Err(Error::Verify { expected: value, got });
+        }
+
+        Ok(())
+    }
+
+    /// The old name of `write_reg_verify`.
+    #[deprecated(note = "renamed to `write_reg_verify`")]
+    pub fn write_verify_reg(&mut self, addr: u8, value: u8) -> Result<(), Error> {
+        self.write_reg_verify(addr, value)
+    }
+
+    /// Write a 16-bit register held in `addr` and `addr + 1`, in the
+    /// `with_endian` byte order: both bytes go out as one
+    /// `write_reg_batch`, so under a single CS.
+    pub fn write_reg16(&mut self, addr: u8, value: u16) -> Result<(), Error> {
+        self.write_wide(addr, value.to_be_bytes())
+    }
+
+    /// Read a 16-bit register written by `write_reg16`.  Each byte is a
+    /// `read_reg` frame, so the addressing mode, CRC and error sentinel
+    /// apply; both go out back to back under one CS, so both halves come
+    /// from the same moment.
+    pub fn read_reg16(&mut self, addr: u8) -> Result<u16, Error> {
+        self.read_wide(addr).map(u16::from_be_bytes)
+    }
+
+    /// Write a 32-bit register held in `addr` to `addr + 3`, like
+    /// `write_reg16`.
+    pub fn write_reg32(&mut self, addr: u8, value: u32) -> Result<(), Error> {
+        self.write_wide(addr, value.to_be_bytes())
+    }
+
+    /// Read a 32-bit register written by `write_reg32`, like `read_reg16`.
+    pub fn read_reg32(&mut self, addr: u8) -> Result<u32, Error> {
+        self.read_wide(addr).map(u32::from_be_bytes)
+    }
+
+    /// Write big-endian `be` to the registers from `addr` up (wrapping),
+    /// reordered for `endian`.
+    fn write_wide<const N: usize>(&mut self, addr: u8, be: [u8; N]) -> Result<(), Error> {
+        let bytes = self.endian.order(be);
+        let mut pairs = [(0u8, 0u8); N];
+        for (i, (pair, b)) in pairs.iter_mut().zip(bytes).enumerate() {
+            *pair = (addr.wrapping_add(i as u8), b);
+        }
+        self.write_reg_batch(&pairs)
+    }
+
+    /// `N` (up to 4) registers from `addr` up, returned big-endian: their
+    /// `read_reg` frames in one `Transfer`, each checked like `read_reg`.
+    /// The mock is idle again after each frame, as in `write_reg_batch`.
+    /// `with_settle_ns` needs a delay inside every frame, so then each
+    /// byte is a `read_reg` of its own.
+    fn read_wide<const N: usize>(&mut self, addr: u8) -> Result<[u8; N], Error> {
+        let mut bytes = [0u8; N];
+        if self.settle_ns != 0 {
+            for (i, b) in bytes.iter_mut().enumerate() {
+                *b = self.read_reg(addr.wrapping_add(i as u8))?;
+            }
+            return Ok(self.endian.order(bytes));
+        }
+
+        let mut tx = [0u8; 4 * 4];
+        let mut rx = [0u8; 4 * 4];
+        let mut len = 0;
+        for i in 0..N {
+            let (frame, n, _) = self.read_reg_tx(addr.wrapping_add(i as u8));
+            tx[len..len + n].copy_from_slice(&frame[..n]);
+            len += n;
+        }
+        self.transact(true, &mut [Operation::Transfer(&mut rx[..len], &tx[..len])])?;
+
+        for (b, reply) in bytes.iter_mut().zip(rx[..len].chunks(len / N)) {
+            let mut frame = [0u8; 4];
+            frame[..reply.len()].copy_from_slice(reply);
+            *b = self.read_reg_value(&frame)?;
+        }
+        Ok(self.endian.order(bytes))
+    }
+
+    /// `read_reg(R::ADDR)`, decoded as `R`.
+    pub fn read<R: Register>(&mut self) -> Result<R, Error> {
+        self.read_reg(R::ADDR).map(R::from_bits)
+    }
+
+    /// `write_reg(R::ADDR, ..)` with `reg`'s bits.
+    pub fn write<R: Register>(&mut self, reg: R) -> Result<(), Error> {
+        self.write_reg(R::ADDR, reg.to_bits())
+    }
+
+    /// Read `addr`, apply `f`, write the result back.
+    pub fn modify_reg(&mut self, addr: u8, f: impl FnOnce(u8) -> u8) -> Result<(), Error> {
+        let value = self.read_reg(addr)?;
+        self.write_reg(addr, f(value))
+    }
+
+    /// Set the bits in `mask`, leaving the rest of the register untouched.
+    pub fn set_reg_bits(&mut self, addr: u8, mask: u8) -> Result<(), Error> {
+        self.modify_reg(addr, |v| v | mask)
+    }
+
+    /// Clear the bits in `mask`, leaving the rest of the register untouched.
+    pub fn clear_reg_bits(&mut self, addr: u8, mask: u8) -> Result<(), Error> {
+        self.modify_reg(addr, |v| v & !mask)
+    }
+
+    /// Poll `addr` until `(value & mask) == expected`, giving up with
+    /// `Error::Timeout` after `max_polls` reads.
+    pub fn wait_for_status(
+        &mut self,
+        addr: u8,
+        mask: u8,
+        expected: u8,
+        max_polls: u32,
+    ) -> Result<(), Error> {
+        self.read_reg_until(addr, |v| v & mask == expected, max_polls).map(|_| ())
+    }
+
+    /// Poll `addr` until the bits in `mask` are all clear (`set == false`)
+    /// or any is set (`set == true`) – "spin until BUSY clears".  Reads go
+    /// through `read_reg`, so CRC checks and retries apply; `Error::Timeout`
+    /// after `max_tries` reads.
+    pub fn wait_reg_bit(&mut self, addr: u8, mask: u8, set: bool, max_tries: u32) -> Result<(), Error> {
+        self.read_reg_until(addr, |v| (v & mask != 0) == set, max_tries).map(|_| ())
+    }
+
+    /// Re-read `addr` until `predicate` accepts the value, and return it –
+    /// e.g. a register the device fills in a while after a write.  Gives up
+    /// with `Error::Timeout` after `max_tries` reads, or after that many
+    /// again for each `with_retries` retry; bus errors end the wait
+    /// straight away.
+    pub fn read_reg_until(
+        &mut self,
+        addr: u8,
+        predicate: impl Fn(u8) -> bool,
+        max_tries: u32,
+    ) -> Result<u8, Error> {
+        for _ in 0..=self.retries {
+            for _ in 0..max_tries {
+                let value = self.read_reg(addr)?;
+                if predicate(value) {
+                    return Ok(value);
+                }
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    /// Write `cmd`, then read `out.len()` bytes, all under one CS assertion
+    /// (`[Operation::Write, Operation::Read]` in a single `transaction`).
+    /// For devices that want the opcode clocked out before any data comes
+    /// back, rather than `read_reg`'s single full-duplex transfer.
+    /// Honours `with_settle_ns` between the two phases.
+    pub fn command_then_read(&mut self, cmd: &[u8], out: &mut [u8]) -> Result<(), Error> {
+        self.last_len = cmd.len() + out.len();
+        let result = if self.settle_ns == 0 {
+            self.spi.transaction(&mut [Operation::Write(cmd), Operation::Read(out)])
+        } else {
+            self.spi.transaction(&mut [
+                Operation::Write(cmd),
+                Operation::DelayNs(self.settle_ns),
+                Operation::Read(out),
+            ])
+        };
+        self.gap();
+        result.map_err(|_| Error::Spi)?;
+        self.check_reply(out)
+    }
+
+    /// `command_then_read` under the generic name: write `tx`, then read
+    /// into `rx`, under one CS assertion.
+    pub fn write_then_read(&mut self, tx: &[u8], rx: &mut [u8]) -> Result<(), Error> {
+        self.command_then_read(tx, rx)
+    }
 
+    /// Diagnostic read of `out.len()` registers from `start` up, streamed
+    /// under one CS.  The address wraps from 0xFF to 0x00, so a dump can
+    /// run past the end of the register file; the Renode mock reads
+    /// registers it doesn't have as 0xFF.
+    pub fn dump(&mut self, start: u8, out: &mut [u8]) -> Result<(), Error> {
+        let op = self.opcodes.dump;
+        let mut header = [0u8; 2];
+        self.transact(true, &mut [Operation::Transfer(&mut header, &[op, start]), Operation::Read(out)])?;
+
+        self.check_reply(&[header[1], out.first().copied().unwrap_or(0)])
+    }
+
+    /// Bring-up aid: burst-read `regs.len()` registers from `start` with
+    /// `dump` and print them to `out` as a 16-per-line hex grid, e.g.
+    /// `dump_regs(0x00, &mut [0; 0x40], &mut console())` for 0x00..=0x3F.
+    /// The bytes stay in `regs` for the caller.
+    pub fn dump_regs(&mut self, start: u8, regs: &mut [u8], out: &mut dyn Output) -> Result<(), Error> {
+        self.dump(start, regs)?;
+        hex_grid(start, regs, &mut |b| out.write_ascii(b));
+        Ok(())
+    }
+
+    /// Burst-read `out.len()` registers from `start` on a device that takes
+    /// the register address directly, with no opcode: one address byte
+    /// (`start | increment_mask | read_mask`), then the data, under one CS.
+    /// The bundled mock wants opcodes, so use `dump` there.  With
+    /// `with_disconnect_check`, all-0xFF or all-0x00 data is an error.
+    /// With `with_crc`, one more byte is clocked after the data and must be
+    /// the CRC-8 of it, else `Error::CrcMismatch`.
+    pub fn read_regs(&mut self, start: u8, out: &mut [u8]) -> Result<(), Error> {
+        let addr = start | self.increment_mask | self.read_mask;
+        let mut crc = [0u8; 1];
+
+        let crc_len = if self.crc { 1 } else { 0 };
+        self.transact(
+            true,
+            &mut [Operation::Write(&[addr]), Operation::Read(out), Operation::Read(&mut crc[..crc_len])],
+        )?;
+        if self.disconnect_check && stuck(out) {
+            return Err(Error::LikelyDisconnected);
+        }
+        self.check_reply(out)?;
+        if self.crc && crc[0] != crc8(out) {
+            return Err(Error::CrcMismatch);
+        }
+        Ok(())
+    }
+
+    /// Burst-write `data` to consecutive registers from `start`, addressed
+    /// like `read_regs` but without `read_mask`.
+    pub fn write_regs(&mut self, start: u8, data: &[u8]) -> Result<(), Error> {
+        let addr = start | self.increment_mask;
+
+        self.transact(false, &mut [Operation::Write(&[addr]), Operation::Write(data)])
+    }
+
+    /// `write_regs` for blocks too long for one burst: `data` goes out as
+    /// `chunk`-byte bursts, each its own transaction, starting `chunk`
+    /// registers past the previous one (wrapping at 0xFF).  `progress` gets
+    /// the bytes written so far after each burst, e.g. to print a `.`.  A
+    /// zero `chunk` is `Error::Length`.
+    pub fn write_stream(
+        &mut self,
+        start: u8,
+        data: &[u8],
+        chunk: usize,
+        mut progress: impl FnMut(usize),
+    ) -> Result<(), Error> {
+        if chunk == 0 {
+            return Err(Error::Length);
+        }
+
+        let mut done = 0;
+        let mut total = 0;
+        for part in data.chunks(chunk) {
+            let result = self.write_regs(start.wrapping_add(done as u8), part);
+            total += self.last_len;
+            self.last_len = total;
+            result?;
+            done += part.len();
+            progress(done);
+        }
+
+        Ok(())
+    }
+
+    /// Set `count` registers from `start` up to `value` in one 4-byte
+    /// command, instead of a write per register.  Addresses wrap like
+    /// `dump`'s.
+    pub fn fill(&mut self, start: u8, count: u8, value: u8) -> Result<(), Error> {
+        let op = self.opcodes.fill;
+        self.transact(false, &mut [Operation::Write(&[op, start, count, value])])
+    }
+
+    /// Soft-reset the device, clearing its register file to zero.
+    pub fn reset(&mut self) -> Result<(), Error> {
+        let op = self.opcodes.reset;
+        self.transact(true, &mut [Operation::Write(&[op])])
+    }
+
+    /// Read the device identity byte; expect `WHO_AM_I_ID` from the mock.
+    pub fn who_am_i(&mut self) -> Result<u8, Error> {
+        let mut rx = [0u8; 2];
+
+        let op = self.opcodes.who_am_i;
+        self.transact(true, &mut [Operation::Transfer(&mut rx, &[op, 0x0])])?;
+        self.check_reply(&rx[1..])?;
+
+        // A mock that predates WhoAmI parks in its error state and answers 0xFF.
+        if rx[1] == 0xFF {
+            return Err(Error::UnknownCommand(self.opcodes.who_am_i));
+        }
+
+        Ok(rx[1])
+    }
+
+    /// Liveness check that leaves the register file alone: send `token`,
+    /// expect `token ^ PING_XOR` back.  Returns the reply; anything else is
+    /// `Error::Protocol` – a loopback wire hands back `token` unchanged.
+    pub fn ping(&mut self, token: u8) -> Result<u8, Error> {
+        let mut rx = [0u8; 3];
+
+        let op = self.opcodes.ping;
+        self.transact(true, &mut [Operation::Transfer(&mut rx, &[op, token, 0x0])])?;
+        self.check_reply(&rx[1..])?;
+
+        if rx[2] != token ^ PING_XOR {
+            return Err(Error::Protocol);
+        }
+
+        Ok(rx[2])
+    }
+
+    /// Ask the mock to check its own state machine and register file, to
+    /// tell a broken mock from a broken driver.  `true` if it answered
+    /// `SELF_TEST_PASS`; a mock without the command answers 0xFF, which
+    /// is `Error::UnknownCommand`.
+    pub fn self_test(&mut self) -> Result<bool, Error> {
+        let mut rx = [0u8; 2];
+
+        let op = self.opcodes.self_test;
+        self.transact(true, &mut [Operation::Transfer(&mut rx, &[op, 0x0])])?;
+        self.check_reply(&rx[1..])?;
+
+        if rx[1] == 0xFF {
+            return Err(Error::UnknownCommand(op));
+        }
+
+        Ok(rx[1] == SELF_TEST_PASS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host_stub::{CaptureSpiDevice, FaultyStub, ProtocolStub};
+    use proptest::prelude::*;
+
+    #[test]
+    fn write_reg_then_read_reg_round_trips() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new());
+
+        dev.write_reg(0x03, 0xAB).unwrap();
+
+        assert_eq!(dev.read_reg(0x03).unwrap(), 0xAB);
+        assert_eq!(dev.into_inner().registers[0x03], 0xAB);
+    }
+
+    #[test]
+    fn commands_put_the_documented_frames_on_the_wire() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new());
+
+        dev.write_reg(0x03, 0xAB).unwrap();
+        dev.read_reg(0x03).unwrap();
+        dev.echo(&mut [0x11, 0x22]).unwrap();
+        dev.reset().unwrap();
+
+        let stub = dev.into_inner();
+        assert_eq!(
+            stub.log(),
+            [
+                vec![0x02, 0x03, 0xAB],
+                vec![0x03, 0x03, 0x00],
+                vec![0x01, 0x11, 0x22, 0x00],
+                vec![0x05],
+            ]
+        );
+    }
+
+    #[test]
+    fn raw_frames_need_equal_lengths_unless_padded() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new());
+        let mut rx = [0u8; 3];
+
+        assert!(matches!(dev.raw(&[0x04, 0x00], &mut rx), Err(Error::Length)));
+        dev.raw(&[0x04, 0x00, 0x00], &mut rx).unwrap();
+        assert_eq!(rx[1], WHO_AM_I_ID);
+
+        let mut rx = [0u8; 2];
+        dev.raw_padded(&[0x04], &mut rx).unwrap();
+        assert_eq!(rx[1], WHO_AM_I_ID);
+    }
+
+    #[test]
+    fn borrowing_drivers_take_turns_on_one_bus() {
+        let mut stub = ProtocolStub::new();
+
+        MockSpiDriver::new_ref(&mut stub).write_reg(0x03, 0xAB).unwrap();
+        let mut dev = MockSpiDriver::new_ref(&mut stub);
+        assert_eq!(dev.read_reg(0x03).unwrap(), 0xAB);
+
+        assert_eq!(stub.transactions, 2);
+    }
+
+    #[test]
+    fn raw_transactions_go_through_the_borrowed_spi() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new());
+        let mut rx = [0u8; 2];
+
+        dev.spi_mut().transfer(&mut rx, &[Command::WhoAmI as u8, 0x00]).unwrap();
+
+        assert_eq!(rx[1], WHO_AM_I_ID);
+        assert_eq!(dev.spi().log(), [vec![0x04, 0x00]]);
+        // The driver is still usable afterwards.
+        dev.write_reg(0x03, 0xAB).unwrap();
+    }
+
+    #[test]
+    fn custom_opcodes_replace_the_default_numbering_on_the_wire() {
+        let opcodes = Opcodes { write_reg: 0x82, read_reg: 0x83, reset: 0xF0, ..Opcodes::DEFAULT };
+        let mut dev = MockSpiDriver::with_opcodes(ProtocolStub::new(), opcodes);
+
+        // The stub only knows the default numbering; only the TX log matters.
+        dev.write_reg(0x03, 0xAB).unwrap();
+        dev.read_reg(0x03).unwrap();
+        dev.reset().unwrap();
+
+        assert_eq!(
+            dev.into_inner().log(),
+            [vec![0x82, 0x03, 0xAB], vec![0x83, 0x03, 0x00], vec![0xF0]]
+        );
+    }
+
+    #[test]
+    fn register_frames_match_the_captured_wire_bytes() {
+        let mut dev = MockSpiDriver::new(CaptureSpiDevice::new().with_rx(&[0, 0, 0, 0, 0, 0xAB]));
+
+        dev.write_reg(0x03, 0xAB).unwrap();
+        assert_eq!(dev.read_reg(0x03).unwrap(), 0xAB);
+
+        assert_eq!(dev.into_inner().tx, [0x02, 0x03, 0xAB, 0x03, 0x03, 0x00]);
+    }
+
+    #[test]
+    fn burst_addresses_carry_the_configured_increment_and_read_bits() {
+        let capture = CaptureSpiDevice::new().with_rx(&[0, 0xA1, 0xA2]);
+        let mut dev = MockSpiDriver::new(capture).with_increment_mask(1 << 6).with_read_mask(1 << 7);
+        let mut out = [0u8; 2];
+
+        dev.read_regs(0x10, &mut out).unwrap();
+        dev.write_regs(0x10, &[0x01, 0x02]).unwrap();
+
+        assert_eq!(out, [0xA1, 0xA2]);
+        assert_eq!(dev.into_inner().tx, [0xD0, 0x00, 0x00, 0x50, 0x01, 0x02]);
+    }
+
+    #[test]
+    fn write_stream_advances_the_address_per_chunk() {
+        let mut dev = MockSpiDriver::new(CaptureSpiDevice::new()).with_increment_mask(0);
+        let mut reports = Vec::new();
+
+        dev.write_stream(0x10, &[1, 2, 3, 4, 5], 2, |n| reports.push(n)).unwrap();
+
+        assert_eq!(reports, [2, 4, 5]);
+        assert_eq!(dev.into_inner().tx, [0x10, 1, 2, 0x12, 3, 4, 0x14, 5]);
+        assert!(matches!(
+            MockSpiDriver::new(CaptureSpiDevice::new()).write_stream(0, &[1], 0, |_| {}),
+            Err(Error::Length)
+        ));
+    }
+
+    #[test]
+    fn burst_reads_check_a_trailing_crc_when_enabled() {
+        let data = [0x12, 0x34, 0x56];
+        let good = crc8(&data);
+        let reply = |crc: u8| CaptureSpiDevice::new().with_rx(&[0, 0x12, 0x34, 0x56, crc]);
+        let mut out = [0u8; 3];
+
+        let mut dev = MockSpiDriver::new(reply(good)).with_crc(true);
+        dev.read_regs(0x10, &mut out).unwrap();
+        assert_eq!(out, data);
+        // Address, three data bytes and the CRC byte, all under one CS.
+        assert_eq!(dev.into_inner().tx.len(), 5);
+
+        let mut dev = MockSpiDriver::new(reply(good ^ 0x01)).with_crc(true);
+        assert!(matches!(dev.read_regs(0x10, &mut out), Err(Error::CrcMismatch)));
+    }
+
+    #[test]
+    fn direction_bit_addressing_drops_the_opcode() {
+        let capture = CaptureSpiDevice::new().with_rx(&[0, 0, 0, 0x5A]);
+        let mut dev = MockSpiDriver::new(capture).with_addressing(Addressing::DirectionBit(0x80));
+
+        dev.write_reg(0x0F, 0xAB).unwrap();
+        assert_eq!(dev.read_reg(0x0F).unwrap(), 0x5A);
+
+        assert_eq!(dev.into_inner().tx, [0x0F, 0xAB, 0x8F, 0x00]);
+    }
+
+    #[test]
+    fn an_all_ones_read_is_flagged_only_when_asked() {
+        let floating = || CaptureSpiDevice::new().with_rx(&[0xFF; 8]);
+
+        assert_eq!(MockSpiDriver::new(floating()).read_reg(0x03).unwrap(), 0xFF);
+
+        let mut dev = MockSpiDriver::new(floating()).with_disconnect_check();
+        assert!(matches!(dev.read_reg(0x03), Err(Error::LikelyDisconnected)));
+        assert!(matches!(dev.read_regs(0x10, &mut [0; 4]), Err(Error::LikelyDisconnected)));
+
+        let mut dev = MockSpiDriver::new(ProtocolStub::new()).with_disconnect_check();
+        dev.write_reg(0x03, 0xFF).unwrap();
+        assert_eq!(dev.read_reg(0x03).unwrap(), 0xFF);
+    }
+
+    #[test]
+    fn read_reg_raw_exposes_status_in_the_header() {
+        // A status nibble: BUSY and DRDY set, upper nibble unrelated.
+        const BUSY: u8 = 1 << 3;
+        const DRDY: u8 = 1 << 1;
+
+        let mut stub = ProtocolStub::new();
+        stub.registers[0x03] = 0xAB;
+        stub.read_header = 0x70 | BUSY | DRDY;
+        let mut dev = MockSpiDriver::new(stub);
+
+        let rx = dev.read_reg_raw(0x03).unwrap();
+
+        assert_eq!(rx[1] & 0x0F, BUSY | DRDY);
+        assert_eq!(rx[2], 0xAB);
+        assert_eq!(dev.read_reg(0x03).unwrap(), 0xAB);
+    }
+
+    #[test]
+    fn the_scheduled_failure_surfaces_as_a_bus_error() {
+        let mut dev = MockSpiDriver::new(FaultyStub::new().fail_after(2));
+
+        dev.write_reg(0x03, 0xAB).unwrap();
+        assert_eq!(dev.read_reg(0x03).unwrap(), 0xAB);
+        assert!(matches!(dev.read_reg(0x03), Err(Error::Spi)));
+        assert_eq!(dev.into_inner().calls, 3);
+    }
+
+    #[test]
+    fn retries_recover_reads_but_not_writes() {
+        let mut dev = MockSpiDriver::new(FaultyStub::new().fail_first(2)).with_retries(2);
+
+        assert_eq!(dev.read_reg(0x03).unwrap(), 0x00);
+        assert_eq!(dev.spi().calls, 3);
+
+        let mut dev = MockSpiDriver::new(FaultyStub::new().fail_first(1)).with_retries(2);
+        assert!(matches!(dev.write_reg(0x03, 0xAB), Err(Error::Spi)));
+
+        let mut dev = dev.with_write_retries(true);
+        dev.write_reg(0x03, 0xAB).unwrap();
+        assert_eq!(dev.into_inner().inner.registers[0x03], 0xAB);
+    }
+
+    #[test]
+    fn faults_can_target_one_opcode() {
+        let mut dev = MockSpiDriver::new(FaultyStub::new().fail_on(Command::ReadReg as u8));
+
+        dev.write_reg(0x03, 0xAB).unwrap();
+        assert!(matches!(dev.read_reg(0x03), Err(Error::Spi)));
+        assert_eq!(dev.into_inner().inner.registers[0x03], 0xAB);
+    }
+
+    #[test]
+    fn registers_above_the_renode_file_size_are_addressable() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new());
+
+        dev.write_reg(0xF0, 0x5A).unwrap();
+
+        assert_eq!(dev.read_reg(0xF0).unwrap(), 0x5A);
+        assert_eq!(dev.read_reg(0xF1).unwrap(), 0x00);
+    }
+
+    #[test]
+    fn u16_words_round_trip() {
+        let mut dev: MockSpiDriver<_, u16> = MockSpiDriver::new(ProtocolStub::<u16>::default());
+
+        dev.write_reg(0x0003, 0xBEEF).unwrap();
+
+        assert_eq!(dev.read_reg(0x0003).unwrap(), 0xBEEF);
+        assert_eq!(dev.into_inner().registers[0x03], 0xBEEF);
+    }
+
+    /// Records every `delay_ns` it's asked for.
+    #[derive(Default)]
+    struct Delays(Vec<u32>);
+
+    impl DelayNs for Delays {
+        fn delay_ns(&mut self, ns: u32) {
+            self.0.push(ns);
+        }
+    }
+
+    #[test]
+    fn with_delay_pauses_after_each_command() {
+        let mut delays = Delays::default();
+        let mut dev = MockSpiDriver::new(ProtocolStub::new()).with_delay(&mut delays, 5_000);
+
+        dev.write_reg_verify(0x07, 0xC3).unwrap();
+
+        // One gap after the write, one after the readback.
+        assert_eq!(delays.0, [5_000, 5_000]);
+    }
+
+    #[test]
+    fn write_reg_verify_accepts_a_value_that_sticks() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new());
+
+        dev.write_reg_verify(0x07, 0xC3).unwrap();
+
+        assert_eq!(dev.into_inner().registers[0x07], 0xC3);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn the_old_verify_name_still_forwards() {
+        let mut stub = ProtocolStub::new();
+        stub.read_only = &[0x05];
+        let mut dev = MockSpiDriver::new(stub);
+
+        dev.write_verify_reg(0x04, 0x3C).unwrap();
+        assert!(matches!(dev.write_verify_reg(0x05, 0x3C), Err(Error::Verify { expected: 0x3C, got: 0x00 })));
+    }
+
+    #[test]
+    fn write_reg_verify_reports_a_read_only_register() {
+        let mut stub = ProtocolStub::new();
+        stub.registers[0x0F] = 0x21;
+        stub.read_only = &[0x0F];
+        let mut dev = MockSpiDriver::new(stub);
+
+        let result = dev.write_reg_verify(0x0F, 0x80);
+
+        assert!(matches!(result, Err(Error::Verify { expected: 0x80, got: 0x21 })));
+    }
+
+    #[test]
+    fn set_and_clear_reg_bits_preserve_other_bits() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new());
+        dev.write_reg(0x08, 0b1010_0001).unwrap();
+
+        dev.set_reg_bits(0x08, 0b0000_0110).unwrap();
+        assert_eq!(dev.read_reg(0x08).unwrap(), 0b1010_0111);
+
+        dev.clear_reg_bits(0x08, 0b1000_0001).unwrap();
+        assert_eq!(dev.read_reg(0x08).unwrap(), 0b0010_0110);
+    }
+
+    #[test]
+    fn modify_reg_applies_the_closure_to_the_current_value() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new());
+        dev.write_reg(0x09, 0x0F).unwrap();
+
+        dev.modify_reg(0x09, |v| v.rotate_left(4)).unwrap();
+
+        assert_eq!(dev.read_reg(0x09).unwrap(), 0xF0);
+    }
+
+    /// CTRL at 0x10: ENABLE in bit 0, MODE in bits 7..4.
+    #[derive(Debug, PartialEq)]
+    struct Ctrl {
+        enable: bool,
+        mode: u8,
+    }
+
+    impl Register for Ctrl {
+        const ADDR: u8 = 0x10;
+
+        fn from_bits(bits: u8) -> Self {
+            Ctrl { enable: bits & 0x01 != 0, mode: bits >> 4 }
+        }
+
+        fn to_bits(self) -> u8 {
+            (self.mode << 4) | self.enable as u8
+        }
+    }
+
+    #[test]
+    fn typed_registers_use_their_address_and_encoding() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new());
+
+        dev.write(Ctrl { enable: true, mode: 3 }).unwrap();
+
+        assert_eq!(dev.read::<Ctrl>().unwrap(), Ctrl { enable: true, mode: 3 });
+        assert_eq!(dev.into_inner().registers[0x10], 0x31);
+    }
+
+    /// Status register 0x10 reports READY (bit 0) from the third read on.
+    fn ready_on_third_poll(addr: u8, reads: u32, stored: u8) -> u8 {
+        if addr == 0x10 && reads >= 3 { stored | 0x01 } else { stored }
+    }
+
+    #[test]
+    fn wait_for_status_returns_once_the_bit_flips() {
+        let mut stub = ProtocolStub::new();
+        stub.on_read = Some(ready_on_third_poll);
+        let mut dev = MockSpiDriver::new(stub);
+
+        dev.wait_for_status(0x10, 0x01, 0x01, 5).unwrap();
+
+        assert_eq!(dev.into_inner().reads, 3);
+    }
+
+    #[test]
+    fn wait_for_status_times_out_when_polls_run_out() {
+        let mut stub = ProtocolStub::new();
+        stub.on_read = Some(ready_on_third_poll);
+        let mut dev = MockSpiDriver::new(stub);
+
+        assert!(matches!(dev.wait_for_status(0x10, 0x01, 0x01, 2), Err(Error::Timeout)));
+    }
+
+    #[test]
+    fn retries_restart_a_poll_that_timed_out() {
+        let mut stub = ProtocolStub::new();
+        stub.on_read = Some(ready_on_third_poll);
+        let mut dev = MockSpiDriver::new(stub).with_retries(1);
+
+        dev.wait_for_status(0x10, 0x01, 0x01, 2).unwrap();
+
+        assert_eq!(dev.into_inner().reads, 3);
+    }
+
+    #[test]
+    fn read_reg_until_returns_the_first_accepted_value() {
+        let mut stub = ProtocolStub::new();
+        stub.registers[0x10] = 0x40;
+        stub.on_read = Some(ready_on_third_poll);
+        let mut dev = MockSpiDriver::new(stub);
+
+        assert_eq!(dev.read_reg_until(0x10, |v| v & 0x01 != 0, 5).unwrap(), 0x41);
+        assert!(matches!(dev.read_reg_until(0x10, |v| v == 0, 2), Err(Error::Timeout)));
+    }
+
+    #[test]
+    fn wait_reg_bit_waits_for_set_or_clear() {
+        let mut stub = ProtocolStub::new();
+        stub.on_read = Some(ready_on_third_poll);
+        let mut dev = MockSpiDriver::new(stub);
+
+        dev.wait_reg_bit(0x10, 0x01, false, 1).unwrap();
+        dev.wait_reg_bit(0x10, 0x01, true, 5).unwrap();
+        assert!(matches!(dev.wait_reg_bit(0x10, 0x01, false, 3), Err(Error::Timeout)));
+    }
+
+    #[test]
+    fn who_am_i_returns_the_documented_id() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new());
+
+        assert_eq!(dev.who_am_i().unwrap(), WHO_AM_I_ID);
+    }
+
+    #[test]
+    fn ping_answers_with_the_token_xored() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new());
+
+        assert_eq!(dev.ping(0x3C).unwrap(), 0x3C ^ PING_XOR);
+        assert_eq!(dev.into_inner().log(), [vec![0x08, 0x3C, 0x00]]);
+    }
+
+    #[test]
+    fn self_test_reports_the_mocks_verdict() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new());
+        dev.write_reg(0x00, 0x77).unwrap();
+
+        assert!(dev.self_test().unwrap());
+        // The check borrows a register and puts it back.
+        assert_eq!(dev.read_reg(0x00).unwrap(), 0x77);
+
+        let mut stub = ProtocolStub::new();
+        stub.self_test_fails = true;
+        assert!(!MockSpiDriver::new(stub).self_test().unwrap());
+
+        let opcodes = Opcodes { self_test: 0x0A, ..Opcodes::DEFAULT };
+        let mut dev = MockSpiDriver::with_opcodes(ProtocolStub::new(), opcodes);
+        assert!(matches!(dev.self_test(), Err(Error::UnknownCommand(0x0A))));
+    }
+
+    #[test]
+    fn reset_clears_the_register_file() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new());
+        dev.write_reg(0x03, 0xAB).unwrap();
+
+        dev.reset().unwrap();
+
+        assert_eq!(dev.read_reg(0x03).unwrap(), 0x00);
+    }
+
+    #[test]
+    fn command_then_read_shares_one_transaction() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new());
+        dev.write_reg(0x06, 0x5C).unwrap();
+        let mut out = [0u8; 1];
+
+        dev.command_then_read(&[Command::ReadReg as u8, 0x06], &mut out).unwrap();
+
+        assert_eq!(out, [0x5C]);
+        out = [0u8; 1];
+        dev.write_then_read(&[Command::ReadReg as u8, 0x06], &mut out).unwrap();
+        assert_eq!(out, [0x5C]);
+        // The Read op continues the ReadReg the Write started, so CS can't
+        // have been released in between.
+        let stub = dev.into_inner();
+        assert_eq!(stub.log()[1..], [vec![0x03, 0x06], vec![0x00], vec![0x03, 0x06], vec![0x00]]);
+        assert_eq!(stub.transactions, 3);
+    }
+
+    #[test]
+    fn settle_delay_splits_reads_around_a_delay() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new()).with_settle_ns(300);
+        dev.write_reg(0x06, 0x5C).unwrap();
+
+        assert_eq!(dev.read_reg(0x06).unwrap(), 0x5C);
+        let mut out = [0u8; 1];
+        dev.command_then_read(&[Command::WhoAmI as u8], &mut out).unwrap();
+        assert_eq!(out, [WHO_AM_I_ID]);
+
+        let stub = dev.into_inner();
+        assert_eq!(stub.delays, [300, 300]);
+        assert_eq!(stub.log()[1..3], [vec![0x03, 0x06], vec![0x00]]);
+    }
+
+    #[test]
+    fn read_reg_raw_shows_where_each_word_landed() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new());
+        dev.write_reg(0x02, 0x7E).unwrap();
+
+        assert_eq!(dev.read_reg_raw(0x02).unwrap(), [0x00, 0x00, 0x7E, 0x00]);
+
+        let mut dev = MockSpiDriver::new(ProtocolStub::new().with_crc(true)).with_crc(true);
+        dev.write_reg(0x02, 0x7E).unwrap();
+
+        assert_eq!(dev.read_reg_raw(0x02).unwrap(), [0x00, 0x00, 0x7E, crc8(&[0x7E])]);
+    }
+
+    #[test]
+    fn dump_wraps_from_the_last_register_to_the_first() {
+        let mut stub = ProtocolStub::new();
+        stub.registers[0xFE] = 0xAA;
+        stub.registers[0xFF] = 0xBB;
+        stub.registers[0x00] = 0xCC;
+        stub.registers[0x01] = 0xDD;
+        let mut dev = MockSpiDriver::new(stub);
+        let mut out = [0u8; 4];
+
+        dev.dump(0xFE, &mut out).unwrap();
+
+        assert_eq!(out, [0xAA, 0xBB, 0xCC, 0xDD]);
+    }
+
+    #[test]
+    fn dump_regs_prints_the_grid_to_the_given_output() {
+        let mut stub = ProtocolStub::new();
+        stub.registers[0x10] = 0xAB;
+        stub.registers[0x13] = 0xCD;
+        let mut dev = MockSpiDriver::new(stub);
+        let mut regs = [0u8; 4];
+        let mut log = String::new();
+
+        dev.dump_regs(0x10, &mut regs, &mut log).unwrap();
+
+        assert_eq!(regs, [0xAB, 0x00, 0x00, 0xCD]);
+        assert_eq!(log, "10: AB 00 00 CD\r\n");
+    }
+
+    #[test]
+    fn fill_sets_only_the_requested_range() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new());
+        dev.write_reg(0x0F, 0x11).unwrap();
+        dev.write_reg(0x14, 0x22).unwrap();
+
+        dev.fill(0x10, 4, 0xA5).unwrap();
+
+        let stub = dev.into_inner();
+        assert_eq!(stub.registers[0x10..0x14], [0xA5; 4]);
+        assert_eq!((stub.registers[0x0F], stub.registers[0x14]), (0x11, 0x22));
+    }
+
+    #[test]
+    fn an_error_sentinel_in_the_status_word_becomes_a_typed_error() {
+        let mut stub = ProtocolStub::new();
+        stub.read_header = 0xEE;
+        stub.registers[0x03] = 0x42;
+
+        assert_eq!(MockSpiDriver::new(stub).read_reg(0x03).unwrap(), 0x42);
+
+        let mut stub = ProtocolStub::new();
+        stub.read_header = 0xEE;
+        stub.registers[0x03] = 0x42;
+        let mut dev = MockSpiDriver::new(stub).with_error_sentinel(0xEE);
+        assert!(matches!(dev.read_reg(0x03), Err(Error::MockError(0x42))));
+
+        // The settle delay splits the frame, but the status word is kept.
+        let mut stub = ProtocolStub::new();
+        stub.read_header = 0xEE;
+        stub.registers[0x03] = 0x42;
+        let mut dev = MockSpiDriver::new(stub).with_error_sentinel(0xEE).with_settle_ns(300);
+        assert!(matches!(dev.read_reg(0x03), Err(Error::MockError(0x42))));
+
+        assert_eq!(format!("{}", Error::MockError(0x07)), "mock reported an error: code 0x07");
+    }
+
+    /// A driver with sentinel 0xEE whose bus answers `rx`.
+    fn sentinel_dev(rx: &[u8]) -> MockSpiDriver<CaptureSpiDevice> {
+        MockSpiDriver::new(CaptureSpiDevice::new().with_rx(rx)).with_error_sentinel(0xEE)
+    }
+
+    #[test]
+    fn every_reading_command_checks_the_error_sentinel() {
+        let mut out = [0u8; 2];
+
+        assert!(matches!(sentinel_dev(&[0x00, 0xEE, 0x07]).dump(0x00, &mut out), Err(Error::MockError(0x07))));
+        assert!(matches!(sentinel_dev(&[0x00, 0xEE, 0x07]).read_regs(0x00, &mut out), Err(Error::MockError(0x07))));
+        assert!(matches!(
+            sentinel_dev(&[0x00, 0xEE, 0x07]).command_then_read(&[0x20], &mut out),
+            Err(Error::MockError(0x07))
+        ));
+        assert!(matches!(sentinel_dev(&[0x00, 0xEE, 0x07]).ping(0x31), Err(Error::MockError(0x07))));
+        // A two-word command clocks nothing after the status: reason 0.
+        assert!(matches!(sentinel_dev(&[0x00, 0xEE]).who_am_i(), Err(Error::MockError(0x00))));
+        assert!(matches!(sentinel_dev(&[0x00, 0xEE]).self_test(), Err(Error::MockError(0x00))));
+
+        // Without the sentinel in the status position, replies pass through.
+        assert_eq!(sentinel_dev(&[0x00, WHO_AM_I_ID]).who_am_i().unwrap(), WHO_AM_I_ID);
+        let mut dev = MockSpiDriver::new(CaptureSpiDevice::new().with_rx(&[0x00, 0xEE]));
+        assert_eq!(dev.who_am_i().unwrap(), 0xEE);
+    }
+
+    #[test]
+    fn reg16_round_trips_in_either_byte_order() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new());
+        dev.write_reg16(0x04, 0x1234).unwrap();
+        assert_eq!(dev.read_reg16(0x04).unwrap(), 0x1234);
+        let stub = dev.into_inner();
+        assert_eq!((stub.registers[0x04], stub.registers[0x05]), (0x12, 0x34));
+        assert_eq!(stub.log()[0], [0x02, 0x04, 0x12, 0x02, 0x05, 0x34]);
+
+        let mut dev = MockSpiDriver::new(ProtocolStub::new()).with_endian(Endian::Little);
+        dev.write_reg16(0x04, 0x1234).unwrap();
+        assert_eq!(dev.read_reg16(0x04).unwrap(), 0x1234);
+        assert_eq!(dev.into_inner().log()[0], [0x02, 0x04, 0x34, 0x02, 0x05, 0x12]);
+    }
+
+    #[test]
+    fn reg32_round_trips_in_either_byte_order() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new());
+        dev.write_reg32(0x08, 0xDEAD_BEEF).unwrap();
+        assert_eq!(dev.read_reg32(0x08).unwrap(), 0xDEAD_BEEF);
+        let stub = dev.into_inner();
+        assert_eq!(stub.registers[0x08..0x0C], [0xDE, 0xAD, 0xBE, 0xEF]);
+        assert_eq!(stub.log()[1], [0x03, 0x08, 0x00, 0x03, 0x09, 0x00, 0x03, 0x0A, 0x00, 0x03, 0x0B, 0x00]);
+        assert_eq!(stub.transactions, 2);
+
+        let mut dev = MockSpiDriver::new(ProtocolStub::new()).with_endian(Endian::Little);
+        dev.write_reg32(0x08, 0xDEAD_BEEF).unwrap();
+        assert_eq!(dev.read_reg32(0x08).unwrap(), 0xDEAD_BEEF);
+        let stub = dev.into_inner();
+        assert_eq!(stub.registers[0x08..0x0C], [0xEF, 0xBE, 0xAD, 0xDE]);
+        assert_eq!(
+            stub.log()[0],
+            [0x02, 0x08, 0xEF, 0x02, 0x09, 0xBE, 0x02, 0x0A, 0xAD, 0x02, 0x0B, 0xDE]
+        );
+    }
+
+    #[test]
+    fn wide_reads_get_read_regs_checks() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new().with_crc(true)).with_crc(true);
+        dev.write_reg32(0x08, 0xDEAD_BEEF).unwrap();
+        assert_eq!(dev.read_reg32(0x08).unwrap(), 0xDEAD_BEEF);
+
+        let mut stub = ProtocolStub::new().with_crc(true);
+        stub.corrupt_crc = true;
+        let mut dev = MockSpiDriver::new(stub).with_crc(true);
+        assert!(matches!(dev.read_reg16(0x08), Err(Error::CrcMismatch)));
+
+        let mut stub = ProtocolStub::new();
+        stub.read_header = 0xEE;
+        let mut dev = MockSpiDriver::new(stub).with_error_sentinel(0xEE);
+        assert!(matches!(dev.read_reg16(0x08), Err(Error::MockError(0x00))));
+
+        // Direction-bit addressing: `[addr | read, 0]` per byte, no opcode.
+        let capture = CaptureSpiDevice::new().with_rx(&[0x00, 0x12, 0x00, 0x34]);
+        let mut dev = MockSpiDriver::new(capture).with_addressing(Addressing::DirectionBit(0x80));
+        assert_eq!(dev.read_reg16(0x04).unwrap(), 0x1234);
+        assert_eq!(dev.into_inner().tx, [0x84, 0x00, 0x85, 0x00]);
+
+        // With a settle delay, each byte is a `read_reg` of its own.
+        let mut dev = MockSpiDriver::new(ProtocolStub::new()).with_settle_ns(300);
+        dev.write_reg16(0x04, 0xBEEF).unwrap();
+        assert_eq!(dev.read_reg16(0x04).unwrap(), 0xBEEF);
+        assert_eq!(dev.into_inner().delays, [300, 300]);
+    }
+
+    #[test]
+    fn write_reg_batch_sends_every_pair_under_one_cs() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new());
+
+        dev.write_reg_batch(&[(0x01, 0x11), (0x02, 0x22), (0x0F, 0xFF)]).unwrap();
+
+        let stub = dev.into_inner();
+        assert_eq!(stub.transactions, 1);
+        assert_eq!(stub.log(), [vec![0x02, 0x01, 0x11, 0x02, 0x02, 0x22, 0x02, 0x0F, 0xFF]]);
+        assert_eq!((stub.registers[0x01], stub.registers[0x02], stub.registers[0x0F]), (0x11, 0x22, 0xFF));
+
+        let mut dev = MockSpiDriver::new(ProtocolStub::new().with_crc(true)).with_crc(true);
+        dev.write_reg_batch(&[(0x03, 0x33), (0x04, 0x44)]).unwrap();
+        assert_eq!(dev.read_reg(0x04).unwrap(), 0x44);
+
+        let too_many = [(0u8, 0u8); BATCH_MAX + 1];
+        assert!(matches!(dev.write_reg_batch(&too_many), Err(Error::PayloadTooLong)));
+    }
+
+    #[test]
+    fn write_reg_ack_checks_the_echoed_opcode() {
+        let mut stub = ProtocolStub::new();
+        stub.ack = true;
+        let mut dev = MockSpiDriver::new(stub);
+        dev.write_reg_ack(0x06, 0x3C).unwrap();
+        assert_eq!(dev.into_inner().registers[0x06], 0x3C);
+
+        let mut dev = MockSpiDriver::new(ProtocolStub::new());
+        assert!(matches!(dev.write_reg_ack(0x06, 0x3C), Err(Error::Nack(0x00))));
+
+        // An opcode the device doesn't know: it parks in its error state.
+        let opcodes = Opcodes { write_reg: 0x0A, ..Opcodes::DEFAULT };
+        let mut stub = ProtocolStub::new();
+        stub.ack = true;
+        let mut dev = MockSpiDriver::with_opcodes(stub, opcodes);
+        assert!(matches!(dev.write_reg_ack(0x06, 0x3C), Err(Error::Nack(0xFF))));
+        assert_eq!(dev.into_inner().registers[0x06], 0x00);
+    }
+
+    #[test]
+    fn crc8_matches_the_smbus_check_value() {
+        assert_eq!(crc8(b"123456789"), 0xF4);
+        // One set bit shifts straight into the polynomial.
+        assert_eq!(crc8(&[0x01]), 0x07);
+        assert_eq!(crc8(&[0xFF]), 0xF3);
+        assert_eq!(crc8(&[]), 0x00);
+    }
+
+    #[test]
+    fn crc_protected_write_and_read_round_trip() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new().with_crc(true)).with_crc(true);
+
+        dev.write_reg(0x05, 0x96).unwrap();
+
+        assert_eq!(dev.read_reg(0x05).unwrap(), 0x96);
+        assert_eq!(dev.into_inner().registers[0x05], 0x96);
+    }
+
+    #[test]
+    fn corrupted_read_crc_is_reported() {
+        let mut stub = ProtocolStub::new().with_crc(true);
+        stub.corrupt_crc = true;
+        let mut dev = MockSpiDriver::new(stub).with_crc(true);
+        dev.write_reg(0x05, 0x96).unwrap();
+
+        assert!(matches!(dev.read_reg(0x05), Err(Error::CrcMismatch)));
+    }
+
+    #[test]
+    fn oversized_echo_is_rejected() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new());
+        let mut buf = [0u8; 256];
+
+        assert!(matches!(dev.echo(&mut buf), Err(Error::PayloadTooLong)));
+    }
+
+    #[test]
+    fn echo_with_uses_the_callers_scratch() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new());
+        let mut buf = [0x5Au8; 300];
+        buf[299] = 0x11;
+        let mut scratch = [0u8; 302];
+
+        dev.echo_with(&mut buf, &mut scratch).unwrap();
+        assert_eq!((buf[0], buf[299]), (0x5A, 0x11));
+
+        assert!(matches!(dev.echo_with(&mut buf, &mut scratch[..301]), Err(Error::Length)));
+    }
+
+    #[test]
+    fn last_transfer_len_totals_chunked_commands() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new());
+
+        dev.write_reg(0x01, 0x02).unwrap();
+        assert_eq!(dev.last_transfer_len(), 3);
+
+        // Three frames, each with the opcode and lag word around it.
+        dev.echo_streaming(&mut [0u8; 600]).unwrap();
+        assert_eq!(dev.last_transfer_len(), 600 + 3 * ECHO_OVERHEAD);
+
+        let mut dev = MockSpiDriver::new(CaptureSpiDevice::new());
+        dev.write_stream(0x10, &[0; 5], 2, |_| {}).unwrap();
+        assert_eq!(dev.last_transfer_len(), 5 + 3);
+        dev.read_regs(0x10, &mut [0; 4]).unwrap();
+        assert_eq!(dev.last_transfer_len(), 1 + 4);
+    }
+
+    #[test]
+    fn echo_streaming_splits_long_payloads_into_frames() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new());
+        let mut buf: [u8; 600] = core::array::from_fn(|i| i as u8 ^ 0x5A);
+        let sent = buf;
+
+        dev.echo_streaming(&mut buf).unwrap();
+        assert_eq!(buf, sent);
+
+        // 255 + 255 + 90.
+        assert_eq!(dev.into_inner().log().len(), 3);
+    }
+
+    #[test]
+    fn a_small_echo_buffer_caps_the_payload() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new()).with_echo_buf::<6>();
+
+        let mut fits = [0x11, 0x22, 0x33, 0x44];
+        dev.echo(&mut fits).unwrap();
+        assert_eq!(fits, [0x11, 0x22, 0x33, 0x44]);
+
+        assert!(matches!(dev.echo(&mut [0u8; 5]), Err(Error::PayloadTooLong)));
+        // Rejected before anything reaches the bus.
+        assert_eq!(dev.into_inner().log().len(), 1);
+    }
+
+    #[test]
+    fn errors_display_their_details() {
+        let e = Error::Verify { expected: 0xAB, got: 0x00 };
+        assert_eq!(format!("{e}"), "readback mismatch: expected 0xAB, got 0x00");
+        assert_eq!(format!("{}", Error::UnknownCommand(0x09)), "unknown command 0x09");
+    }
+
+    proptest! {
+        #[test]
+        fn echo_round_trips_any_payload(payload in proptest::collection::vec(any::<u8>(), 0..=255)) {
+            let mut dev = MockSpiDriver::new(ProtocolStub::new());
+            let mut buf = payload.clone();
+
+            dev.echo(&mut buf).unwrap();
+
+            prop_assert_eq!(buf, payload);
+        }
+    }
+
+    #[test]
+    fn echo_returns_the_payload() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new());
+        let mut buf = [0x11, 0x22, 0x33];
+
+        dev.echo(&mut buf).unwrap();
+
+        assert_eq!(buf, [0x11, 0x22, 0x33]);
+    }
+}
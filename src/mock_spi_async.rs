@@ -0,0 +1,126 @@
+//! Async flavour of `MockSpiDriver`, over `embedded_hal_async::spi::SpiDevice`.
+//!
+//! Frames are built and decoded by the same helpers as the blocking driver
+//! in `mock_spi.rs`, so the two can only differ in where they await.
+
+#![allow(dead_code)]
+
+use core::marker::PhantomData;
+
+use embedded_hal_async::spi::{Operation, SpiDevice};
+
+use crate::mock_spi::{
+    ECHO_WIRE_LEN, Error, Opcodes, Word, echo_frame, read_reg_frame, read_reg_response, write_reg_frame,
+};
+
+pub struct MockSpiDriverAsync<SPI, W = u8> {
+    spi: SPI,
+    opcodes: Opcodes,
+    crc: bool,
+    _word: PhantomData<W>,
+}
+
+impl<SPI: SpiDevice<W>, W: Word> MockSpiDriverAsync<SPI, W> {
+    pub fn new(spi: SPI) -> Self {
+        Self::with_opcodes(spi, Opcodes::DEFAULT)
+    }
+
+    /// See `MockSpiDriver::with_opcodes`.
+    pub fn with_opcodes(spi: SPI, opcodes: Opcodes) -> Self {
+        Self { spi, opcodes, crc: false, _word: PhantomData }
+    }
+
+    /// See `MockSpiDriver::with_crc`.
+    pub fn with_crc(mut self, enabled: bool) -> Self {
+        self.crc = enabled;
+        self
+    }
+
+    pub fn into_inner(self) -> SPI {
+        self.spi
+    }
+
+    pub fn spi(&self) -> &SPI {
+        &self.spi
+    }
+
+    /// Borrow the bus for raw `transaction`s, as `MockSpiDriver::spi_mut`.
+    pub fn spi_mut(&mut self) -> &mut SPI {
+        &mut self.spi
+    }
+
+    pub async fn echo(&mut self, buf: &mut [W]) -> Result<(), Error> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        let (mut wire, len) = echo_frame::<W, ECHO_WIRE_LEN>(self.opcodes.echo, buf)?;
+
+        self.spi
+            .transfer_in_place(&mut wire[..len])
+            .await
+            .map_err(|_| Error::Spi)?;
+
+        buf.copy_from_slice(&wire[2..len]);
+
+        Ok(())
+    }
+
+    pub async fn write_reg(&mut self, addr: W, value: W) -> Result<(), Error> {
+        let (frame, len) = write_reg_frame(self.opcodes.write_reg, addr, value, self.crc);
+
+        self.spi
+            .transaction(&mut [Operation::Write(&frame[..len])])
+            .await
+            .map_err(|_| Error::Spi)
+    }
+
+    pub async fn read_reg(&mut self, addr: W) -> Result<W, Error> {
+        let (tx, len) = read_reg_frame(self.opcodes.read_reg, addr, self.crc);
+        let mut rx = [W::default(); 4];
+
+        self.spi
+            .transaction(&mut [Operation::Transfer(&mut rx[..len], &tx[..len])])
+            .await
+            .map_err(|_| Error::Spi)?;
+
+        read_reg_response(&rx, self.crc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host_stub::ProtocolStub;
+
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+
+    /// The stub never actually waits, so one poll always finishes.
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        let mut cx = Context::from_waker(Waker::noop());
+        match pin!(fut).poll(&mut cx) {
+            Poll::Ready(out) => out,
+            Poll::Pending => panic!("stub future returned Pending"),
+        }
+    }
+
+    #[test]
+    fn async_write_reg_then_read_reg_round_trips() {
+        let mut dev = MockSpiDriverAsync::new(ProtocolStub::new());
+
+        block_on(dev.write_reg(0x03, 0xAB)).unwrap();
+
+        assert_eq!(block_on(dev.read_reg(0x03)).unwrap(), 0xAB);
+    }
+
+    #[test]
+    fn async_echo_returns_the_payload() {
+        let mut dev = MockSpiDriverAsync::new(ProtocolStub::new());
+        let mut buf = [0x11, 0x22, 0x33];
+
+        block_on(dev.echo(&mut buf)).unwrap();
+
+        assert_eq!(buf, [0x11, 0x22, 0x33]);
+    }
+}
@@ -0,0 +1,105 @@
+//! Where the test log goes: USART2 by default, an RTT up channel with the
+//! `rtt` feature.
+//!
+//! `console()` is the backend the `uart_*` shorthands, the `TestRunner` in
+//! `main` and the suite print through, so switching backends doesn't touch
+//! the test sequence.  Input (`uart_read_byte`) stays on USART2 either way.
+//!
+//! The `host-stub` build prints to stdout instead (`Stdout`).
+//!
+//! RTT needs `Rtt::init()` once before the first print; until then output
+//! is dropped.  Its channel doesn't block: if the probe isn't reading,
+//! output that doesn't fit is dropped rather than stalling the tests.
+
+#![allow(dead_code)]
+
+use crate::uart::{Uart, format_u32};
+
+// Links in the single-core `critical-section` impl `rtt-target` locks with.
+#[cfg(feature = "rtt")]
+use cortex_m as _;
+
+/// A sink for the ASCII test log.
+pub trait Output {
+    fn write_str(&mut self, s: &str);
+
+    /// `s`, then `\r\n` like `Uart::println`.
+    fn write_line(&mut self, s: &str) {
+        self.write_str(s);
+        self.write_str("\r\n");
+    }
+
+    /// One ASCII byte, e.g. from `report::Hex`.  Anything else is dropped.
+    fn write_ascii(&mut self, b: u8) {
+        if let Ok(s) = core::str::from_utf8(core::slice::from_ref(&b)) {
+            self.write_str(s);
+        }
+    }
+
+    /// `v` in decimal.
+    fn write_u32(&mut self, v: u32) {
+        let mut buf = [0u8; 10];
+        for &b in format_u32(v, &mut buf) {
+            self.write_ascii(b);
+        }
+    }
+}
+
+impl Output for Uart {
+    fn write_str(&mut self, s: &str) {
+        self.print(s);
+    }
+
+    fn write_ascii(&mut self, b: u8) {
+        self.write_byte(b);
+    }
+}
+
+/// RTT up channel 0 ("Terminal"), through `rtt-target`.
+#[cfg(feature = "rtt")]
+#[derive(Debug, Copy, Clone)]
+pub struct Rtt;
+
+#[cfg(feature = "rtt")]
+impl Rtt {
+    /// Set up the RTT control block and the print channel.  Call once, first.
+    pub fn init() {
+        rtt_target::rtt_init_print!();
+    }
+}
+
+#[cfg(feature = "rtt")]
+impl Output for Rtt {
+    fn write_str(&mut self, s: &str) {
+        rtt_target::rprint!(s);
+    }
+}
+
+/// The host's stdout, for the `host-stub` build.
+#[cfg(feature = "host-stub")]
+#[derive(Debug, Copy, Clone)]
+pub struct Stdout;
+
+#[cfg(feature = "host-stub")]
+impl Output for Stdout {
+    fn write_str(&mut self, s: &str) {
+        print!("{s}");
+    }
+}
+
+#[cfg(not(any(feature = "rtt", feature = "host-stub")))]
+pub type Console = Uart;
+#[cfg(all(feature = "rtt", not(feature = "host-stub")))]
+pub type Console = Rtt;
+#[cfg(feature = "host-stub")]
+pub type Console = Stdout;
+
+/// The log backend this build prints through.
+pub const fn console() -> Console {
+    #[cfg(not(any(feature = "rtt", feature = "host-stub")))]
+    return crate::uart::USART2;
+    #[cfg(all(feature = "rtt", not(feature = "host-stub")))]
+    return Rtt;
+    #[cfg(feature = "host-stub")]
+    return Stdout;
+}
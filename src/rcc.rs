@@ -0,0 +1,55 @@
+//! RCC peripheral clock gates for the blocks this crate drives.
+//!
+//! STM32F4 RCC register map (offsets from 0x4002_3800):
+//!   +0x30  AHB1ENR  – GPIOA is bit 0, DMA2 is bit 22
+//!   +0x40  APB1ENR  – USART2 is bit 17
+//!   +0x44  APB2ENR  – USART1 is bit 4, SPI1 is bit 12
+//!
+//! On the F4 the GPIO ports hang off AHB1; APB2ENR bit 0 is TIM1 (it's
+//! the F1 that puts GPIOA on APB2).
+
+const RCC_BASE: u32 = 0x4002_3800;
+
+pub const AHB1ENR: u32 = RCC_BASE + 0x30;
+pub const APB1ENR: u32 = RCC_BASE + 0x40;
+pub const APB2ENR: u32 = RCC_BASE + 0x44;
+
+pub const AHB1ENR_GPIOAEN:  u32 = 1 << 0;
+#[cfg(feature = "dma")]
+pub const AHB1ENR_DMA2EN:   u32 = 1 << 22;
+pub const APB1ENR_USART2EN: u32 = 1 << 17;
+pub const APB2ENR_USART1EN: u32 = 1 << 4;
+pub const APB2ENR_SPI1EN:   u32 = 1 << 12;
+
+/// Set `bits` in the enable register at `reg`, leaving the other gates as
+/// they were, so enabling a clock twice is harmless.
+pub fn enable(reg: u32, bits: u32) {
+    unsafe {
+        let current = core::ptr::read_volatile(reg as *const u32);
+        core::ptr::write_volatile(reg as *mut u32, with_enabled(current, bits));
+
+        // Erratum 2.1.13: the peripheral isn't clocked until two cycles
+        // after the write.  Reading the register back covers that.
+        core::ptr::read_volatile(reg as *const u32);
+    }
+}
+
+const fn with_enabled(current: u32, bits: u32) -> u32 {
+    current | bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enabling_keeps_other_gates_and_is_idempotent() {
+        let before = APB2ENR_USART1EN;
+
+        let once = with_enabled(before, APB2ENR_SPI1EN);
+        let twice = with_enabled(once, APB2ENR_SPI1EN);
+
+        assert_eq!(once, APB2ENR_USART1EN | APB2ENR_SPI1EN);
+        assert_eq!(twice, once);
+    }
+}
@@ -0,0 +1,288 @@
+//! Pass/fail tally for the on-target test sequence.
+//!
+//! `finish()` prints `Results: N passed, M failed` and then exactly one of
+//! `ALL_PASSED` / `SOME_FAILED` on its own line – CI greps the UART log for
+//! them, so keep the strings stable.  `RUN_BEGIN` (printed by `main` once
+//! the UART is up) and `RUN_END` (last line of `finish()`) bracket the run,
+//! so a log parser can slice it out of any boot chatter around it.
+//!
+//! `check_eq!` covers the common "compare and report" case in one line.
+
+use crate::output::Output;
+use crate::uart::Uart;
+
+pub const RESULTS_PREFIX: &str = "Results: ";
+pub const ALL_PASSED: &str = "All tests passed.";
+pub const SOME_FAILED: &str = "SOME TESTS FAILED.";
+pub const RUN_BEGIN: &str = "=== TEST RUN BEGIN ===";
+pub const RUN_END: &str = "=== TEST RUN END ===";
+
+/// Values `check_eq!` can print on a mismatch: `0xAB`, `0xBEEF`, `[11 22 33]`.
+pub trait Hex {
+    /// Feed the ASCII hex rendering to `out`, one byte at a time.
+    fn hex(&self, out: &mut dyn FnMut(u8));
+}
+
+fn hex_digits(b: u8, out: &mut dyn FnMut(u8)) {
+    const HEX: &[u8] = b"0123456789ABCDEF";
+    out(HEX[(b >> 4) as usize]);
+    out(HEX[(b & 0x0F) as usize]);
+}
+
+impl Hex for u8 {
+    fn hex(&self, out: &mut dyn FnMut(u8)) {
+        out(b'0');
+        out(b'x');
+        hex_digits(*self, out);
+    }
+}
+
+impl Hex for u16 {
+    fn hex(&self, out: &mut dyn FnMut(u8)) {
+        let [hi, lo] = self.to_be_bytes();
+        out(b'0');
+        out(b'x');
+        hex_digits(hi, out);
+        hex_digits(lo, out);
+    }
+}
+
+impl Hex for [u8] {
+    fn hex(&self, out: &mut dyn FnMut(u8)) {
+        out(b'[');
+        for (i, &b) in self.iter().enumerate() {
+            if i > 0 {
+                out(b' ');
+            }
+            hex_digits(b, out);
+        }
+        out(b']');
+    }
+}
+
+impl<const N: usize> Hex for [u8; N] {
+    fn hex(&self, out: &mut dyn FnMut(u8)) {
+        self[..].hex(out);
+    }
+}
+
+impl<T: Hex + ?Sized> Hex for &T {
+    fn hex(&self, out: &mut dyn FnMut(u8)) {
+        (**self).hex(out);
+    }
+}
+
+/// Registers per `hex_grid` row.
+pub const GRID_COLUMNS: usize = 16;
+
+/// Render `regs`, read from `start` up, as a register map: 16 per line,
+/// each line led by its first address (wrapping like the registers do).
+///
+/// ```text
+/// 00: AB 00 00 ... 00
+/// 10: 00 00 00 ... 00
+/// ```
+pub fn hex_grid(start: u8, regs: &[u8], out: &mut dyn FnMut(u8)) {
+    for (row, chunk) in regs.chunks(GRID_COLUMNS).enumerate() {
+        hex_digits(start.wrapping_add((row * GRID_COLUMNS) as u8), out);
+        out(b':');
+        for &b in chunk {
+            out(b' ');
+            hex_digits(b, out);
+        }
+        out(b'\r');
+        out(b'\n');
+    }
+}
+
+/// Print `expected X, got Y` and end the line – the failure detail
+/// `check_eq!` and the suite's table tests share, so CI sees one format.
+pub fn print_mismatch<T: Hex + ?Sized, O: Output + ?Sized>(out: &mut O, actual: &T, expected: &T) {
+    out.write_str("expected ");
+    expected.hex(&mut |b| out.write_ascii(b));
+    out.write_str(", got ");
+    actual.hex(&mut |b| out.write_ascii(b));
+    out.write_line("");
+}
+
+/// `check_eq!(runner, actual, expected, "label")` – compare, print
+/// `[PASS] label` or `[FAIL] label: expected .., got ..` and count the
+/// result.  Works for `u8`, `u16`, `[u8; N]` and `&[u8]`; evaluates to
+/// whether the check passed.
+#[cfg_attr(feature = "host-stub", allow(unused_macros))]
+macro_rules! check_eq {
+    ($runner:expr, $actual:expr, $expected:expr, $label:expr $(,)?) => {
+        $runner.check_eq(&$actual, &$expected, $label)
+    };
+}
+// Only the firmware main imports it by path; host tests see the macro
+// textually, and the `host-stub` main doesn't use it.
+#[cfg_attr(any(test, feature = "host-stub"), allow(unused_imports))]
+pub(crate) use check_eq;
+
+#[derive(Debug)]
+pub struct TestRunner<O = Uart> {
+    out: O,
+    pub passed: u32,
+    pub failed: u32,
+}
+
+impl<O: Output> TestRunner<O> {
+    /// A runner printing its verdict lines to `out`.
+    pub const fn new(out: O) -> Self {
+        Self { out, passed: 0, failed: 0 }
+    }
+
+    /// Where the verdict lines go, for detail printed alongside them.
+    pub fn out(&mut self) -> &mut O {
+        &mut self.out
+    }
+
+    /// Count a pass and print `[PASS] name`.
+    pub fn pass(&mut self, name: &str) {
+        self.record(true);
+        self.out.write_str("[PASS] ");
+        self.out.write_line(name);
+    }
+
+    /// Count a failure and print `[FAIL] reason`.
+    pub fn fail(&mut self, reason: &str) {
+        self.record(false);
+        self.out.write_str("[FAIL] ");
+        self.out.write_line(reason);
+    }
+
+    /// The body of `check_eq!`.
+    pub fn check_eq<T: Hex + PartialEq + ?Sized>(&mut self, actual: &T, expected: &T, label: &str) -> bool {
+        if actual == expected {
+            self.pass(label);
+            return true;
+        }
+
+        self.record(false);
+        self.out.write_str("[FAIL] ");
+        self.out.write_str(label);
+        self.out.write_str(": ");
+        print_mismatch(&mut self.out, actual, expected);
+        false
+    }
+
+    fn record(&mut self, ok: bool) {
+        if ok {
+            self.passed += 1;
+        } else {
+            self.failed += 1;
+        }
+    }
+
+    pub fn all_passed(&self) -> bool {
+        self.failed == 0
+    }
+
+    /// Print `"Results: N passed, M failed"`, the verdict line, then `RUN_END`.
+    pub fn finish(&mut self) {
+        let (passed, failed, verdict) =
+            (self.passed, self.failed, if self.all_passed() { ALL_PASSED } else { SOME_FAILED });
+        self.out.write_str(RESULTS_PREFIX);
+        self.out.write_u32(passed);
+        self.out.write_str(" passed, ");
+        self.out.write_u32(failed);
+        self.out.write_line(" failed");
+        self.out.write_line(verdict);
+        self.out.write_line(RUN_END);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Collects the log so runner output can be checked off-target.
+    impl Output for String {
+        fn write_str(&mut self, s: &str) {
+            self.push_str(s);
+        }
+    }
+
+    #[test]
+    fn a_single_failure_fails_the_run() {
+        let mut runner = TestRunner::new(String::new());
+        runner.record(true);
+        assert!(runner.all_passed());
+
+        runner.record(false);
+        runner.record(true);
+
+        assert_eq!((runner.passed, runner.failed), (2, 1));
+        assert!(!runner.all_passed());
+    }
+
+    #[test]
+    fn the_log_carries_the_lines_ci_greps_for() {
+        let mut runner = TestRunner::new(String::new());
+
+        runner.pass("echo");
+        runner.check_eq(&0x12u8, &0x34, "read_reg");
+        runner.finish();
+
+        let log: Vec<&str> = runner.out.split("\r\n").collect();
+        assert_eq!(
+            log,
+            [
+                "[PASS] echo",
+                "[FAIL] read_reg: expected 0x34, got 0x12",
+                "Results: 1 passed, 1 failed",
+                SOME_FAILED,
+                RUN_END,
+                "",
+            ]
+        );
+    }
+
+    fn render<T: Hex + ?Sized>(v: &T) -> String {
+        let mut s = String::new();
+        v.hex(&mut |b| s.push(b as char));
+        s
+    }
+
+    #[test]
+    fn hex_renders_each_checkable_type() {
+        let bytes: &[u8] = &[0x11, 0x22, 0x33];
+
+        assert_eq!(render(&0x0Au8), "0x0A");
+        assert_eq!(render(&0xBEEFu16), "0xBEEF");
+        assert_eq!(render(&[0xDEu8, 0xAD]), "[DE AD]");
+        assert_eq!(render(&bytes), "[11 22 33]");
+        assert_eq!(render(&[0u8; 0]), "[]");
+    }
+
+    #[test]
+    fn hex_grid_breaks_rows_at_16_and_wraps_addresses() {
+        let mut regs = [0u8; 20];
+        regs[0] = 0xAB;
+        regs[16] = 0xCD;
+        let mut s = String::new();
+
+        hex_grid(0xF8, &regs, &mut |b| s.push(b as char));
+
+        let lines: Vec<&str> = s.split("\r\n").collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], format!("F8: AB{}", " 00".repeat(15)));
+        assert_eq!(lines[1], "08: CD 00 00 00");
+        assert_eq!(lines[2], "");
+    }
+
+    #[test]
+    fn check_eq_accepts_bytes_words_arrays_and_slices() {
+        let mut runner = TestRunner::new(String::new());
+        let got: &[u8] = &[0x11, 0x22];
+
+        assert!(check_eq!(runner, 0xABu8, 0xAB, "u8"));
+        assert!(!check_eq!(runner, 0x1234u16, 0x4321, "u16"));
+        assert!(check_eq!(runner, [1u8, 2, 3], [1, 2, 3], "array"));
+        assert!(!check_eq!(runner, got, &[0x11, 0x23][..], "slice"));
+
+        assert_eq!((runner.passed, runner.failed), (2, 2));
+        assert!(runner.out.contains("[FAIL] slice: expected [11 23], got [11 22]\r\n"));
+    }
+}
@@ -1,103 +1,421 @@
-//! Bare-metal `SpiDevice<u8>` backed by STM32F4 SPI1 hardware registers.
+//! Bare-metal `SpiDevice<u8>` / `SpiDevice<u16>` backed by STM32F4 SPI1
+//! hardware registers.
 //!
 //! This is the missing HAL layer: it lets `mock_spi::MockSpiDriver` (which
 //! speaks `embedded_hal::spi::SpiDevice`) actually toggle SPI1's CR1/DR
 //! registers and a GPIO CS pin.
 //!
 //! Register map used:
-//!   SPI1 base         = 0x4000_5000
+//!   SPI1 base         = 0x4001_3000
 //!     +0x00  CR1      – control 1  (SPE, MSTR, BR, SSM, SSI, …)
-//!     +0x04  CR2      – control 2  (FRXTH)
-//!     +0x08  SR       – status     (TXE bit 1, RXNE bit 0, BSY bit 7)
-//!     +0x0C  DR       – data       (byte-wide access for 8-bit frames)
+//!     +0x04  CR2      – control 2  (SSOE; RXNEIE/TXEIE belong to `irq`)
+//!     +0x08  SR       – status     (TXE bit 1, RXNE bit 0, MODF bit 5, OVR bit 6,
+//!                                   BSY bit 7)
+//!     +0x0C  DR       – data       (byte-wide access for 8-bit frames,
+//!                                   halfword for 16-bit)
 //!
-//!   GPIOA base        = 0x4000_8000
+//!   GPIOA base        = 0x4002_0000
+//!     +0x00  MODER    – pin modes      (real-hardware only)
+//!     +0x04  OTYPER   – output type    (real-hardware only)
 //!     +0x18  BSRR     – bit set/reset  (CS toggle)
+//!     +0x20  AFRL     – AF for pins 0..7 (real-hardware only)
 //!
-//! CS pin = PA4 (bit 4) – matches the STM32F4 Discovery kit's default
-//! SPI1 NSS mapping.  The .repl file attaches the mock to spi1, so CS
-//! transitions are what trigger FinishTransmission() in the C# mock.
+//! CS pin = PA4 (bit 4) by default – matches the STM32F4 Discovery kit's
+//! default SPI1 NSS mapping.  The .repl file attaches the mock to spi1, so
+//! CS transitions are what trigger FinishTransmission() in the C# mock.
+//! Software CS is active-low unless `Stm32SpiConfig::cs_polarity` says
+//! `ActiveHigh`.
+//!
+//! `init_with(Stm32SpiConfig)` takes every option; `init()` and friends are
+//! shorthands for common configs.  Two NSS modes are supported: software
+//! NSS via `init()` (SSM/SSI plus the CS GPIO toggling above – what the
+//! bundled .repl expects) and hardware NSS via `init_hw_nss()` (SSOE, no
+//! GPIO writes).
+//!
+//! `MultiCsDevice` wraps a software-NSS device to share SPI1 among several
+//! slaves, each on its own CS line (`CsPin`, any GPIO port).
+//!
+//! `init_bidi()` selects three-wire half-duplex (BIDIMODE): one data line,
+//! with BIDIOE flipped between the write and read phases of a transaction.
+//! Reads clock each frame with a dummy DR write, as Renode's model expects;
+//! that's a Renode-only path (see `init_bidi`).
+//!
+//! `Operation::DelayNs` is honoured through a `DelayNs` impl held by the
+//! device: `SpinDelay` (calibrated against `CORE_CLOCK_HZ`) by default, or
+//! anything passed to `with_delay()` – e.g. `systick::SysTickDelay`, which
+//! times the wait with the SysTick counter instead of loop iterations.
+//!
+//! `try_transfer_byte()` is the same byte exchange without the spin: it
+//! returns `nb::Error::WouldBlock` until SPI1 is ready, for superloops.
+//! The blocking paths are `nb::block!` over the same step.
+//!
+//! `irq` runs one transfer at a time from the SPI1 interrupt instead:
+//! `start_transaction`, then `is_done` / `take_result`, with
+//! `irq::on_spi1_interrupt` hooked to the SPI1 vector.  With the
+//! `interrupt` feature, `transaction_irq` streams through the same vector:
+//! the ISR queues RX while the caller keeps TX going.
+//!
+//! With the `dma` feature, operations of `dma::THRESHOLD` bytes or more are
+//! moved by DMA2 (stream 0 RX, stream 3 TX, channel 3) instead of the
+//! byte-at-a-time loop.  Shorter ones, and everything in BIDIMODE, stay PIO.
+//!
+//! `Stm32SpiConfig::frame_16bit` switches to 16-bit frames for
+//! `SpiDevice<u16>` (e.g. `MockSpiDriver<_, u16>`).  The F4 family –
+//! F405/407 and F411 alike – sizes frames with CR1.DFF (8 or 16 bits,
+//! only writable with SPE clear).  The CR2 DS[3:0] field that other STM32
+//! SPIs (F0/F3/F7/L4) use for 4..16-bit frames doesn't exist here, and
+//! neither does their FRXTH FIFO threshold: CR2 bit 6 is RXNEIE on the F4,
+//! so neither width sets it.  Operations must match the configured width;
+//! the other one fails with `FrameSize`.  16-bit frames always go PIO.
+//!
+//! Every status wait also watches MODF (mode fault): if NSS is pulled low
+//! under a master, the SPI clears MSTR/SPE and TXE/RXNE would never come.
+//! Init and transactions report it as `Stm32SpiError::ModeFault` instead,
+//! after the RM0090 clear sequence (the SR read that saw MODF, then a CR1
+//! write) so the flag doesn't stick.  MSTR/SPE stay cleared until the next
+//! `init_with`.
+//! That's mostly a real-hardware concern – a glitch on the NSS pin – since
+//! Renode's NSS never moves on its own.
 
 #![allow(dead_code)]
 
-use embedded_hal::spi::{ErrorKind, Operation, SpiDevice};
+use embedded_hal::delay::DelayNs;
+use embedded_hal::spi::{ErrorKind, MODE_0, Mode, Operation, Phase, Polarity, SpiDevice};
+
+use crate::rcc;
 
 // ---------------------------------------------------------------------------
 // Constants
 // ---------------------------------------------------------------------------
 
 const SPI1_BASE: u32 = 0x4001_3000;
-const SPI1_CR1:  u32 = SPI1_BASE + 0x00;
+const SPI1_CR1:  u32 = SPI1_BASE;
 const SPI1_CR2:  u32 = SPI1_BASE + 0x04;
 const SPI1_SR:   u32 = SPI1_BASE + 0x08;
 const SPI1_DR:   u32 = SPI1_BASE + 0x0C;
+const SPI1_CRCPR: u32 = SPI1_BASE + 0x10;
+
+const GPIOA_BASE:   u32 = 0x4002_0000;
+const GPIOA_MODER:  u32 = GPIOA_BASE;
+const GPIOA_OTYPER: u32 = GPIOA_BASE + 0x04;
+const GPIOA_BSRR:   u32 = GPIOA_BASE + 0x18;
+const GPIOA_AFRL:   u32 = GPIOA_BASE + 0x20;
+
+/// SPI1_NSS pin index within GPIOA, and the default software CS.  PA4 = bit 4.
+const NSS_PIN: u32 = 4;
 
-const GPIOA_BASE: u32 = 0x4000_8000;
-const GPIOA_BSRR: u32 = GPIOA_BASE + 0x18;
+/// RCC enable register and bit for everything SPI1 needs: GPIOA, SPI1,
+/// and DMA2 with the `dma` feature.
+const CLOCK_GATES: &[(u32, u32)] = &[
+    (rcc::AHB1ENR, rcc::AHB1ENR_GPIOAEN),
+    (rcc::APB2ENR, rcc::APB2ENR_SPI1EN),
+    #[cfg(feature = "dma")]
+    (rcc::AHB1ENR, rcc::AHB1ENR_DMA2EN),
+];
 
-/// CS pin index within GPIOA.  PA4 = bit 4.
-const CS_PIN: u32 = 4;
+/// SCK, MISO, MOSI = PA5, PA6, PA7.
+const SPI_PINS: [u32; 3] = [5, 6, 7];
+
+// MODER: two bits per pin (pin n at bits 2n+1..2n)
+const MODER_OUTPUT: u32 = 0b01;
+const MODER_AF:     u32 = 0b10;
+
+// AFRL: four bits per pin (pin n at bits 4n+3..4n); AF5 = SPI1/SPI2
+const AF5_SPI1: u32 = 5;
+
+/// Core clock `SpinDelay::default()` is calibrated against.  The F4 runs
+/// from the 16 MHz HSI out of reset, and nothing here switches to the PLL.
+pub const CORE_CLOCK_HZ: u32 = 16_000_000;
 
 // CR1 bits
+const CR1_CPHA:  u32 = 1 << 0;
+const CR1_CPOL:  u32 = 1 << 1;
 const CR1_MSTR:  u32 = 1 << 2;
 const CR1_SPE:   u32 = 1 << 6;
+const CR1_LSBFIRST: u32 = 1 << 7;
 const CR1_SSM:   u32 = 1 << 9;   // software slave management
 const CR1_SSI:   u32 = 1 << 8;   // internal slave select (must be 1 when SSM=1 in master)
-// BR[2:0] at bits 5..3 – we use 0b111 (/256) to keep it slow and safe in sim
-const CR1_BR_SLOWEST: u32 = 0b111 << 3;
+const CR1_DFF:   u32 = 1 << 11;  // data frame format (1 = 16-bit)
+const CR1_CRCNEXT: u32 = 1 << 12; // send the CRC after the frame in DR
+const CR1_CRCEN:   u32 = 1 << 13; // hardware CRC calculation
+const CR1_BIDIOE:   u32 = 1 << 14;  // bidirectional output enable (1 = transmit)
+const CR1_BIDIMODE: u32 = 1 << 15;  // 1-line bidirectional data mode
+// BR[2:0] at bits 5..3 – see `Prescaler`
+const CR1_BR_SHIFT: u32 = 3;
 
 // CR2 bits
-const CR2_FRXTH: u32 = 1 << 6;   // FIFO threshold = 1 byte (needed for 8-bit reads on F4)
+const CR2_SSOE:  u32 = 1 << 2;   // SS output enable (hardware NSS in master mode)
 
 // SR bits
 const SR_RXNE: u32 = 1 << 0;
 const SR_TXE:  u32 = 1 << 1;
+const SR_CRCERR: u32 = 1 << 4;
+const SR_MODF: u32 = 1 << 5;
+const SR_OVR:  u32 = 1 << 6;
 const SR_BSY:  u32 = 1 << 7;
 
 // ---------------------------------------------------------------------------
 // Volatile helpers
 // ---------------------------------------------------------------------------
+//
+// Host tests swap these for `fake`, a model of SPI1 good enough to run
+// whole transactions against.
 
+#[cfg(not(test))]
 #[inline(always)]
 unsafe fn rd(addr: u32) -> u32 {
-    core::ptr::read_volatile(addr as *const u32)
+    unsafe { core::ptr::read_volatile(addr as *const u32) }
 }
 
+#[cfg(not(test))]
 #[inline(always)]
 unsafe fn wr(addr: u32, val: u32) {
-    core::ptr::write_volatile(addr as *mut u32, val);
+    unsafe { core::ptr::write_volatile(addr as *mut u32, val) }
 }
 
-/// Byte-sized volatile write to DR (important: on F4 with FRXTH=1 you must
-/// write only the low byte, not the full 32-bit word, to keep the 8-bit
-/// frame size in effect).
+/// Byte-sized volatile write to DR, for 8-bit frames (DFF=0).
+#[cfg(not(test))]
 #[inline(always)]
 unsafe fn wr_byte(addr: u32, val: u8) {
-    core::ptr::write_volatile(addr as *mut u8, val);
+    unsafe { core::ptr::write_volatile(addr as *mut u8, val) }
+}
+
+/// Byte-sized volatile read from DR, for 8-bit frames (clears RXNE).
+#[cfg(not(test))]
+#[inline(always)]
+unsafe fn rd_byte(addr: u32) -> u8 {
+    unsafe { core::ptr::read_volatile(addr as *const u8) }
+}
+
+/// Halfword volatile write to DR, for 16-bit frames (DFF=1).
+#[cfg(not(test))]
+#[inline(always)]
+unsafe fn wr_hword(addr: u32, val: u16) {
+    unsafe { core::ptr::write_volatile(addr as *mut u16, val) }
 }
 
-/// Byte-sized volatile read from DR (clears RXNE on F4 when FRXTH=1).
+/// Halfword volatile read from DR, for 16-bit frames (DFF=1).
+#[cfg(not(test))]
 #[inline(always)]
+unsafe fn rd_hword(addr: u32) -> u16 {
+    unsafe { core::ptr::read_volatile(addr as *const u16) }
+}
+
+#[cfg(test)]
+unsafe fn rd(addr: u32) -> u32 {
+    fake::read(addr)
+}
+
+#[cfg(test)]
+unsafe fn wr(addr: u32, val: u32) {
+    fake::write(addr, val)
+}
+
+#[cfg(test)]
+unsafe fn wr_byte(addr: u32, val: u8) {
+    fake::write(addr, val as u32)
+}
+
+#[cfg(test)]
 unsafe fn rd_byte(addr: u32) -> u8 {
-    core::ptr::read_volatile(addr as *const u8)
+    fake::read(addr) as u8
+}
+
+#[cfg(test)]
+unsafe fn wr_hword(addr: u32, val: u16) {
+    fake::write(addr, val as u32)
+}
+
+#[cfg(test)]
+unsafe fn rd_hword(addr: u32) -> u16 {
+    fake::read(addr) as u16
 }
 
 // ---------------------------------------------------------------------------
 // Error type
 // ---------------------------------------------------------------------------
 
-#[derive(Debug, Copy, Clone)]
-pub struct Stm32SpiError;
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Stm32SpiError {
+    /// MODF: NSS went low while SPI1 was master, so the hardware cleared
+    /// MSTR and SPE.
+    ModeFault,
+    /// A full-duplex operation in BIDIMODE, which has one data line.
+    HalfDuplex,
+    /// DMA2 flagged a transfer error (`dma` feature).
+    Dma,
+    /// `u8` operations on a 16-bit device, or `u16` on an 8-bit one.
+    FrameSize,
+    /// An interrupt-driven transfer is still in flight or hasn't been
+    /// collected with `take_result`.
+    Busy,
+    /// More bytes than an interrupt-driven transfer holds (`IRQ_BUF`).
+    TooLong,
+    /// `transaction_irq`'s RX queue was full when a byte arrived, so the
+    /// byte was dropped.
+    RxOverflow,
+    /// CRCERR: the CRC frame received after a transaction didn't match
+    /// the peripheral's own CRC over the received data.
+    Crc,
+    /// `MultiCsDevice` was handed a hardware-NSS device, which drives its
+    /// own NSS pin instead of the `CsPin`s.
+    HardwareNss,
+    /// `MultiCsDevice::select` got an index past its CS lines.
+    NoSuchCs,
+}
+
+impl Stm32SpiError {
+    /// Short, allocation-free description, for printing without `core::fmt`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Stm32SpiError::ModeFault => "mode fault (MODF)",
+            Stm32SpiError::HalfDuplex => "full-duplex operation in BIDIMODE",
+            Stm32SpiError::Dma => "DMA transfer error",
+            Stm32SpiError::FrameSize => "word size doesn't match the frame size",
+            Stm32SpiError::Busy => "interrupt transfer already pending",
+            Stm32SpiError::TooLong => "transfer longer than IRQ_BUF",
+            Stm32SpiError::RxOverflow => "RX queue overflow",
+            Stm32SpiError::Crc => "CRC error (CRCERR)",
+            Stm32SpiError::HardwareNss => "MultiCsDevice needs software NSS",
+            Stm32SpiError::NoSuchCs => "no such chip-select",
+        }
+    }
+}
 
 impl embedded_hal::spi::Error for Stm32SpiError {
     fn kind(&self) -> ErrorKind {
-        ErrorKind::Other
+        match self {
+            Stm32SpiError::ModeFault => ErrorKind::ModeFault,
+            _ => ErrorKind::Other,
+        }
     }
 }
 
 impl core::fmt::Display for Stm32SpiError {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        write!(f, "STM32 SPI error")
+        f.write_str(self.as_str())
+    }
+}
+
+/// The fault a status word reports, if any.
+const fn sr_fault(sr: u32) -> Result<(), Stm32SpiError> {
+    if sr & SR_MODF != 0 {
+        Err(Stm32SpiError::ModeFault)
+    } else {
+        Ok(())
+    }
+}
+
+/// `Crc` if `sr` has CRCERR set.
+const fn crc_status(sr: u32) -> Result<(), Stm32SpiError> {
+    if sr & SR_CRCERR != 0 {
+        Err(Stm32SpiError::Crc)
+    } else {
+        Ok(())
+    }
+}
+
+/// Second half of the MODF clear sequence; the SR read that found MODF
+/// was the first.  Writes CR1 back as the hardware left it.
+#[inline(always)]
+unsafe fn clear_modf() {
+    unsafe { wr(SPI1_CR1, rd(SPI1_CR1)) }
+}
+
+/// `sr_fault`, clearing MODF if that's what `sr` reports.
+#[inline(always)]
+unsafe fn check_sr(sr: u32) -> Result<(), Stm32SpiError> {
+    let fault = sr_fault(sr);
+    if fault.is_err() {
+        unsafe { clear_modf() }
+    }
+    fault
+}
+
+/// One non-blocking step of a single-frame exchange, given a fresh `sr`.
+/// `sent` tracks whether the frame is already in DR: TXE lets `write` run,
+/// then RXNE lets `read` finish the exchange.  Until the flag it needs
+/// sets the step is `WouldBlock` – unless SR reports a mode fault, which
+/// runs `clear` and fails rather than waiting for a flag the disabled
+/// master will never raise.  A ready flag wins over the fault.
+fn exchange_step<W>(
+    sent: &mut bool,
+    sr: u32,
+    write: impl FnOnce(),
+    read: impl FnOnce() -> W,
+    clear: impl FnOnce(),
+) -> nb::Result<W, Stm32SpiError> {
+    if !*sent && sr & SR_TXE != 0 {
+        write();
+        *sent = true;
+        // RXNE can't be set by the SR read that came before the write.
+        return Err(nb::Error::WouldBlock);
+    }
+    if *sent && sr & SR_RXNE != 0 {
+        *sent = false;
+        return Ok(read());
+    }
+    if let Err(e) = sr_fault(sr) {
+        clear();
+        *sent = false;
+        return Err(nb::Error::Other(e));
+    }
+    Err(nb::Error::WouldBlock)
+}
+
+/// A whole single-frame exchange: `exchange_step` against `read_sr` until
+/// it finishes or faults.  Taking SR as a closure keeps the flag handling
+/// off the registers, so the host tests can feed it a scripted status.
+fn exchange<W>(
+    mut read_sr: impl FnMut() -> u32,
+    mut write: impl FnMut(),
+    mut read: impl FnMut() -> W,
+    mut clear: impl FnMut(),
+) -> Result<W, Stm32SpiError> {
+    let mut sent = false;
+    nb::block!(exchange_step(&mut sent, read_sr(), &mut write, &mut read, &mut clear))
+}
+
+/// Poll `read_sr` until TXE is set and BSY clear: the last frame written
+/// has left DR and finished shifting out.  No MODF check – a faulted
+/// master drops SPE, which clears BSY anyway.
+fn drain(mut read_sr: impl FnMut() -> u32) {
+    while read_sr() & (SR_TXE | SR_BSY) != SR_TXE {}
+}
+
+// ---------------------------------------------------------------------------
+// SpinDelay – default DelayNs for Operation::DelayNs
+// ---------------------------------------------------------------------------
+
+/// Busy-wait `DelayNs`.  Spins one iteration per core cycle the delay
+/// spans; each iteration takes at least a cycle, so delays only run long.
+#[derive(Debug, Copy, Clone)]
+pub struct SpinDelay {
+    core_hz: u32,
+}
+
+impl SpinDelay {
+    pub const fn new(core_hz: u32) -> Self {
+        Self { core_hz }
+    }
+
+    /// Core cycles in `ns` nanoseconds, rounded up.
+    const fn cycles(&self, ns: u32) -> u32 {
+        (ns as u64 * self.core_hz as u64).div_ceil(1_000_000_000) as u32
+    }
+}
+
+impl Default for SpinDelay {
+    fn default() -> Self {
+        Self::new(CORE_CLOCK_HZ)
+    }
+}
+
+impl DelayNs for SpinDelay {
+    fn delay_ns(&mut self, ns: u32) {
+        for _ in 0..self.cycles(ns) {
+            core::hint::spin_loop();
+        }
     }
 }
 
@@ -105,112 +423,2203 @@ impl core::fmt::Display for Stm32SpiError {
 // Stm32Spi1Device – implements SpiDevice<u8>
 // ---------------------------------------------------------------------------
 
-/// A zero-sized handle.  All state lives in the hardware registers.
-pub struct Stm32Spi1Device;
+/// How chip-select is driven.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Nss {
+    /// SSM=1, SSI=1; CS is PA4, toggled through GPIOA BSRR.
+    Software,
+    /// SSM=0, SSOE=1; the SPI drives NSS itself.  On F4, NSS is low for as
+    /// long as SPE=1, so each transaction brackets itself with SPE.
+    Hardware,
+}
 
-impl Stm32Spi1Device {
-    /// Configure SPI1 for Mode 0, 8-bit, master, software NSS.
-    ///
-    /// Call this once after RCC has clocked SPI1 and GPIOA.  It does NOT
-    /// configure GPIO pin modes / alternate functions – Renode's STM32
-    /// model routes SPI1 signals without explicit GPIO AF setup, so we
-    /// skip that step in simulation.
-    pub fn init() {
-        unsafe {
-            // Write CR1 with SPE=0 first (many F4 errata require config
-            // while peripheral is disabled)
-            let cr1 = CR1_MSTR | CR1_SSM | CR1_SSI | CR1_BR_SLOWEST;
-            wr(SPI1_CR1, cr1);
+/// Which level of a software CS line selects the slave.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum CsPolarity {
+    /// Low selects – the usual SPI convention, and what the bundled .repl
+    /// expects.
+    #[default]
+    ActiveLow,
+    /// High selects.
+    ActiveHigh,
+}
+
+/// The GPIO BSRR word that drives `pin` to the selected level (`asserted`)
+/// or the idle one.  BSRR bits [15:0] set a pin, bits [31:16] reset it.
+const fn cs_bsrr(pin: u8, polarity: CsPolarity, asserted: bool) -> u32 {
+    let high = match polarity {
+        CsPolarity::ActiveLow => !asserted,
+        CsPolarity::ActiveHigh => asserted,
+    };
+    if high { 1 << pin } else { 1 << (16 + pin) }
+}
+
+/// SCK = f_PCLK2 / n.  The discriminant is the CR1 BR[2:0] value.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Prescaler {
+    Div2 = 0,
+    Div4 = 1,
+    Div8 = 2,
+    Div16 = 3,
+    Div32 = 4,
+    Div64 = 5,
+    Div128 = 6,
+    Div256 = 7,
+}
 
-            // CR2: FRXTH=1 so 8-bit reads work
-            wr(SPI1_CR2, CR2_FRXTH);
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BitOrder {
+    MsbFirst,
+    LsbFirst,
+}
 
-            // Now enable
-            wr(SPI1_CR1, cr1 | CR1_SPE);
+/// Everything `Stm32Spi1Device::init_with` sets up.
+///
+/// `Default` is what `init()` has always configured: Mode 0, /256 (slow and
+/// safe in sim), CS on PA4, MSB-first, software NSS, full duplex, 8-bit
+/// frames.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Stm32SpiConfig {
+    pub mode: Mode,
+    pub prescaler: Prescaler,
+    /// GPIOA pin (0..=15, not PA5–PA7) toggled as CS with software NSS.
+    /// Hardware NSS always comes out on PA4.
+    pub cs_pin: u8,
+    pub bit_order: BitOrder,
+    pub nss: Nss,
+    /// Three-wire half duplex (BIDIMODE).  Transactions may then only
+    /// contain `Write`, `Read` and `DelayNs` operations.
+    pub bidi: bool,
+    /// 16-bit frames (DFF) for `SpiDevice<u16>` instead of 8-bit ones for
+    /// `SpiDevice<u8>`.
+    pub frame_16bit: bool,
+    /// Level of `cs_pin` that selects the slave.  Software NSS only; the
+    /// SPI's own NSS output is always active-low.
+    pub cs_polarity: CsPolarity,
+    /// Hardware CRC (CRCEN) with this CRCPR polynomial: every transaction
+    /// ends with a CRC frame each way, and a bad one is `Stm32SpiError::Crc`.
+    pub crc_poly: Option<u16>,
+}
 
-            // Pull CS high (inactive) to start clean
-            Self::cs_high();
+impl Default for Stm32SpiConfig {
+    fn default() -> Self {
+        Self {
+            mode: MODE_0,
+            prescaler: Prescaler::Div256,
+            cs_pin: NSS_PIN as u8,
+            bit_order: BitOrder::MsbFirst,
+            nss: Nss::Software,
+            bidi: false,
+            frame_16bit: false,
+            cs_polarity: CsPolarity::ActiveLow,
+            crc_poly: None,
         }
     }
+}
 
-    // -- CS control via GPIOA BSRR -------------------------------------------
+/// Chained setters over `Default`, finished by `apply()`:
+/// `Stm32SpiConfig::default().with_mode(MODE_3).with_nss(Nss::Hardware).apply()?`.
+impl Stm32SpiConfig {
+    pub const fn with_mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub const fn with_prescaler(mut self, prescaler: Prescaler) -> Self {
+        self.prescaler = prescaler;
+        self
+    }
+
+    pub const fn with_cs_pin(mut self, pin: u8) -> Self {
+        self.cs_pin = pin;
+        self
+    }
+
+    pub const fn with_bit_order(mut self, bit_order: BitOrder) -> Self {
+        self.bit_order = bit_order;
+        self
+    }
+
+    pub const fn with_nss(mut self, nss: Nss) -> Self {
+        self.nss = nss;
+        self
+    }
+
+    pub const fn with_bidi(mut self, bidi: bool) -> Self {
+        self.bidi = bidi;
+        self
+    }
+
+    pub const fn with_frame_16bit(mut self, frame_16bit: bool) -> Self {
+        self.frame_16bit = frame_16bit;
+        self
+    }
+
+    pub const fn with_cs_polarity(mut self, polarity: CsPolarity) -> Self {
+        self.cs_polarity = polarity;
+        self
+    }
+
+    pub const fn with_crc_poly(mut self, poly: u16) -> Self {
+        self.crc_poly = Some(poly);
+        self
+    }
+
+    /// Configure SPI1 with this: CR1 and CR2 are written once each while
+    /// SPE is clear.  Shorthand for `Stm32Spi1Device::init_with(self)`.
+    pub fn apply(self) -> Result<Stm32Spi1Device, Stm32SpiError> {
+        Stm32Spi1Device::init_with(self)
+    }
+}
+
+/// CR1 (with SPE clear) and CR2 for a master configured by `cfg`.
+const fn control_words(cfg: &Stm32SpiConfig) -> (u32, u32) {
+    let mut cr1 = CR1_MSTR | ((cfg.prescaler as u32) << CR1_BR_SHIFT);
+    if let Polarity::IdleHigh = cfg.mode.polarity {
+        cr1 |= CR1_CPOL;
+    }
+    if let Phase::CaptureOnSecondTransition = cfg.mode.phase {
+        cr1 |= CR1_CPHA;
+    }
+    if let BitOrder::LsbFirst = cfg.bit_order {
+        cr1 |= CR1_LSBFIRST;
+    }
+    if cfg.crc_poly.is_some() {
+        cr1 |= CR1_CRCEN;
+    }
+    if cfg.bidi {
+        // Idle transmitting, so the line isn't left floating as an input.
+        cr1 |= CR1_BIDIMODE | CR1_BIDIOE;
+    }
+    if cfg.frame_16bit {
+        cr1 |= CR1_DFF;
+    }
+
+    match cfg.nss {
+        Nss::Software => (cr1 | CR1_SSM | CR1_SSI, 0),
+        Nss::Hardware => (cr1, CR2_SSOE),
+    }
+}
+
+/// `(register, value)` writes that configure SPI1 as `cfg` describes, in
+/// order, all with SPE clear: CRCPR (with CRC), then CR1, then CR2.
+fn config_writes(cfg: &Stm32SpiConfig) -> impl Iterator<Item = (u32, u32)> {
+    let (cr1, cr2) = control_words(cfg);
+    cfg.crc_poly
+        .map(|poly| (SPI1_CRCPR, poly as u32))
+        .into_iter()
+        .chain([(SPI1_CR1, cr1), (SPI1_CR2, cr2)])
+}
+
+/// Frames `op` clocks; a `Transfer` runs for its longer slice, as
+/// embedded-hal specifies.
+fn frames<W>(op: &Operation<'_, W>) -> usize {
+    match op {
+        Operation::Write(buf) => buf.len(),
+        Operation::Read(buf) => buf.len(),
+        Operation::Transfer(rx, tx) => rx.len().max(tx.len()),
+        Operation::TransferInPlace(buf) => buf.len(),
+        Operation::DelayNs(_) => 0,
+    }
+}
+
+/// Count one frame off `left`: true for the last one, after which CRCNEXT
+/// is due.  A `left` of zero (no CRC) never reports a last frame.
+fn last_frame(left: &mut usize) -> bool {
+    if *left == 0 {
+        return false;
+    }
+    *left -= 1;
+    *left == 0
+}
+
+/// GPIOA MODER with PA5/PA6/PA7 in AF mode, and either `cs_pin` as a
+/// plain output (software NSS) or PA4 in AF mode (SPI1_NSS, hardware NSS).
+/// Other pins keep their bits from `moder`.
+const fn gpio_moder(moder: u32, nss: Nss, cs_pin: u8) -> u32 {
+    let mut moder = moder;
+    let mut i = 0;
+    while i < SPI_PINS.len() {
+        let shift = SPI_PINS[i] * 2;
+        moder = (moder & !(0b11 << shift)) | (MODER_AF << shift);
+        i += 1;
+    }
+
+    let (pin, mode) = match nss {
+        Nss::Software => (cs_pin as u32, MODER_OUTPUT),
+        Nss::Hardware => (NSS_PIN, MODER_AF),
+    };
+    (moder & !(0b11 << (pin * 2))) | (mode << (pin * 2))
+}
+
+/// GPIOA AFRL with AF5 on PA5/PA6/PA7, plus PA4 for hardware NSS.  Other
+/// pins keep their bits from `afrl`.
+const fn gpio_afrl(afrl: u32, nss: Nss) -> u32 {
+    let mut afrl = afrl;
+    let mut i = 0;
+    while i < SPI_PINS.len() {
+        let shift = SPI_PINS[i] * 4;
+        afrl = (afrl & !(0xF << shift)) | (AF5_SPI1 << shift);
+        i += 1;
+    }
+
+    match nss {
+        Nss::Software => afrl,
+        Nss::Hardware => (afrl & !(0xF << (NSS_PIN * 4))) | (AF5_SPI1 << (NSS_PIN * 4)),
+    }
+}
+
+/// GPIOA OTYPER with `cs_pin` push-pull (bit clear) rather than open-drain.
+const fn gpio_otyper(otyper: u32, cs_pin: u8) -> u32 {
+    otyper & !(1 << cs_pin)
+}
+
+/// Data-line direction in BIDIMODE.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Direction {
+    Transmit,
+    Receive,
+}
+
+/// `cr1` with BIDIOE set for `dir`.
+const fn with_direction(cr1: u32, dir: Direction) -> u32 {
+    match dir {
+        Direction::Transmit => cr1 | CR1_BIDIOE,
+        Direction::Receive => cr1 & !CR1_BIDIOE,
+    }
+}
+
+/// Direction the single data line needs for `op`, `None` if it doesn't
+/// move data.  Full-duplex operations can't run on one wire.
+fn bidi_direction<W>(op: &Operation<'_, W>) -> Result<Option<Direction>, Stm32SpiError> {
+    match op {
+        Operation::Write(_) => Ok(Some(Direction::Transmit)),
+        Operation::Read(_) => Ok(Some(Direction::Receive)),
+        Operation::DelayNs(_) => Ok(None),
+        Operation::Transfer(..) | Operation::TransferInPlace(_) => Err(Stm32SpiError::HalfDuplex),
+    }
+}
+
+/// One DR access: `u8` for 8-bit frames, `u16` for 16-bit (DFF=1).  Every
+/// frame-sized DR access goes through here, so the width is picked by the
+/// type; the `u8` impl inlines to the same `wr_byte` / `rd_byte` as before.
+trait SpiWord: Copy {
+    const SIXTEEN_BIT: bool;
+    /// Clocked out by `Read` operations.
+    const DUMMY: Self;
+
+    unsafe fn write_dr(self);
+    unsafe fn read_dr() -> Self;
+
+    /// Move `op` by DMA if it qualifies; `None` leaves it to the PIO loop.
+    #[cfg(feature = "dma")]
+    unsafe fn try_dma(op: &mut Operation<'_, Self>) -> Option<Result<(), Stm32SpiError>>;
+}
+
+impl SpiWord for u8 {
+    const SIXTEEN_BIT: bool = false;
+    const DUMMY: Self = 0x00;
 
-    /// CS low = active (assert).  BSRR bits [31:16] are reset bits.
     #[inline(always)]
-    unsafe fn cs_low() {
-        wr(GPIOA_BSRR, 1 << (16 + CS_PIN));
+    unsafe fn write_dr(self) {
+        unsafe { wr_byte(SPI1_DR, self) }
     }
 
-    /// CS high = inactive (deassert).  BSRR bits [15:0] are set bits.
     #[inline(always)]
-    unsafe fn cs_high() {
-        wr(GPIOA_BSRR, 1 << CS_PIN);
+    unsafe fn read_dr() -> Self {
+        unsafe { rd_byte(SPI1_DR) }
     }
 
-    // -- Core transfer -------------------------------------------------------
+    #[cfg(feature = "dma")]
+    unsafe fn try_dma(op: &mut Operation<'_, u8>) -> Option<Result<(), Stm32SpiError>> {
+        dma::eligible(op).then(|| unsafe { dma::run(op) })
+    }
+}
+
+impl SpiWord for u16 {
+    const SIXTEEN_BIT: bool = true;
+    const DUMMY: Self = 0x0000;
+
+    #[inline(always)]
+    unsafe fn write_dr(self) {
+        unsafe { wr_hword(SPI1_DR, self) }
+    }
 
-    /// Full-duplex single-byte exchange: wait TXE, write, wait RXNE, read.
     #[inline(always)]
-    unsafe fn transfer_byte(tx: u8) -> u8 {
-        // Wait for transmit buffer empty
-        while rd(SPI1_SR) & SR_TXE == 0 {}
-        // Byte-write to DR
-        wr_byte(SPI1_DR, tx);
-        // Wait for receive buffer not empty
-        while rd(SPI1_SR) & SR_RXNE == 0 {}
-        // Byte-read from DR
-        rd_byte(SPI1_DR)
+    unsafe fn read_dr() -> Self {
+        unsafe { rd_hword(SPI1_DR) }
+    }
+
+    /// The DMA streams are set up for byte transfers only.
+    #[cfg(feature = "dma")]
+    unsafe fn try_dma(_op: &mut Operation<'_, u16>) -> Option<Result<(), Stm32SpiError>> {
+        None
     }
 }
 
-// ---------------------------------------------------------------------------
-// SpiDevice impl
-// ---------------------------------------------------------------------------
+/// An asserted chip-select, deasserted when dropped so no return path out
+/// of a transaction – `?` included – can leave CS low.
+struct CsGuard {
+    nss: Nss,
+    cs_pin: u8,
+    cs_polarity: CsPolarity,
+}
 
-impl embedded_hal::spi::ErrorType for Stm32Spi1Device {
-    type Error = Stm32SpiError;
+impl CsGuard {
+    /// Start a transaction: assert CS, or let hardware NSS fall by enabling SPE.
+    #[inline(always)]
+    unsafe fn select(nss: Nss, cs_pin: u8, cs_polarity: CsPolarity) -> Self {
+        unsafe {
+            match nss {
+                Nss::Software => <Stm32Spi1Device>::cs_assert(cs_pin, cs_polarity),
+                Nss::Hardware => wr(SPI1_CR1, rd(SPI1_CR1) | CR1_SPE),
+            }
+        }
+        Self { nss, cs_pin, cs_polarity }
+    }
 }
 
-impl SpiDevice<u8> for Stm32Spi1Device {
-    fn transaction(
-        &mut self,
-        operations: &mut [Operation<'_, u8>],
-    ) -> Result<(), Stm32SpiError> {
+impl Drop for CsGuard {
+    /// End the transaction.  The last frame must finish shifting (`flush`)
+    /// before CS deasserts, or before SPE drops and hardware NSS rises.
+    fn drop(&mut self) {
         unsafe {
-            Self::cs_low();
+            drain(|| rd(SPI1_SR));
+            match self.nss {
+                Nss::Software => <Stm32Spi1Device>::cs_deassert(self.cs_pin, self.cs_polarity),
+                Nss::Hardware => wr(SPI1_CR1, rd(SPI1_CR1) & !CR1_SPE),
+            }
+        }
+    }
+}
 
-            for op in operations.iter_mut() {
-                match op {
-                    Operation::Write(buf) => {
-                        for &b in buf.iter() {
-                            Self::transfer_byte(b); // discard RX
-                        }
-                    }
-                    Operation::Read(buf) => {
-                        for slot in buf.iter_mut() {
-                            *slot = Self::transfer_byte(0x00); // dummy TX
-                        }
-                    }
-                    Operation::Transfer(rx, tx) => {
-                        // True simultaneous full-duplex
-                        for (r, &t) in rx.iter_mut().zip(tx.iter()) {
-                            *r = Self::transfer_byte(t);
-                        }
-                    }
-                    Operation::TransferInPlace(buf) => {
-                        for slot in buf.iter_mut() {
-                            *slot = Self::transfer_byte(*slot);
-                        }
-                    }
-                    Operation::DelayNs(_) => {
-                        // No-op in simulation – Renode's SPI model is
-                        // cycle-accurate, no real timing gaps needed.
-                    }
-                }
+/// A handle to SPI1.  Apart from the NSS and duplex modes, the frame size,
+/// the CS pin and the delay used for `Operation::DelayNs`, all state lives
+/// in the hardware registers.
+pub struct Stm32Spi1Device<D = SpinDelay> {
+    nss: Nss,
+    bidi: bool,
+    frame_16bit: bool,
+    cs_pin: u8,
+    cs_polarity: CsPolarity,
+    /// CRCEN is set: `transaction` sends and checks a CRC frame.
+    crc: bool,
+    delay: D,
+    /// `try_transfer_byte` has written its frame and waits on RXNE.
+    sent: bool,
+}
+
+impl Stm32Spi1Device {
+    /// Configure SPI1 for Mode 0, 8-bit, master, software NSS –
+    /// `init_with(Stm32SpiConfig::default())`.
+    ///
+    /// Call this once.  Clocks (`enable_clocks`) and GPIO pin modes /
+    /// alternate functions (`configure_gpio`) are only set up with the
+    /// `real-hardware` feature – Renode's STM32 model clocks everything and
+    /// routes SPI1 signals without them, so simulation runs skip that step.
+    pub fn init() -> Result<Self, Stm32SpiError> {
+        Self::init_with(Stm32SpiConfig::default())
+    }
+
+    /// Like `init()`, but with hardware NSS (SSOE) instead of the PA4 GPIO.
+    /// SPE stays clear until a transaction starts, so NSS idles high.
+    pub fn init_hw_nss() -> Result<Self, Stm32SpiError> {
+        Stm32SpiConfig::default().with_nss(Nss::Hardware).apply()
+    }
+
+    /// Like `init()`, but three-wire half-duplex (BIDIMODE).  Transactions
+    /// may only contain `Write`, `Read` and `DelayNs` operations.
+    ///
+    /// This targets Renode only.  `Read` clocks each frame with a dummy DR
+    /// write, the way Renode's SPI model moves data in either mode.  On
+    /// silicon (RM0090 28.3.5), clearing BIDIOE with SPE set starts the
+    /// clock running with no DR writes at all, so the extra frames overrun
+    /// between reads, and the BSY waits in `set_direction` and before CS
+    /// deasserts don't hold.  Real hardware would need the receive-only
+    /// sequence instead: SPE cleared after the second-to-last RXNE.
+    pub fn init_bidi() -> Result<Self, Stm32SpiError> {
+        Stm32SpiConfig::default().with_bidi(true).apply()
+    }
+
+    /// Like `init()`, but 16-bit frames (DFF) for `SpiDevice<u16>`, e.g.
+    /// `MockSpiDriver::<_, u16>::new(Stm32Spi1Device::init_16bit()?)`.
+    pub fn init_16bit() -> Result<Self, Stm32SpiError> {
+        Stm32SpiConfig::default().with_frame_16bit(true).apply()
+    }
+
+    /// Like `init()`, but with the peripheral's CRC (CRCEN) over polynomial
+    /// `poly`: each transaction sends its CRC after the last frame and
+    /// fails with `Stm32SpiError::Crc` if the one clocked back is wrong.
+    /// The bundled mock doesn't speak CRC.
+    pub fn init_with_crc(poly: u16) -> Result<Self, Stm32SpiError> {
+        Stm32SpiConfig::default().with_crc_poly(poly).apply()
+    }
+
+    /// Clock GPIOA (AHB1ENR) and SPI1 (APB2ENR), plus DMA2 (AHB1ENR) with
+    /// the `dma` feature.  Renode clocks everything from reset, but real
+    /// silicon ignores register writes to a gated peripheral.  Idempotent.
+    pub fn enable_clocks() {
+        for &(reg, bit) in CLOCK_GATES {
+            rcc::enable(reg, bit);
+        }
+    }
+
+    /// Route SPI1 to its pins: PA5/PA6/PA7 to AF5 (SCK/MISO/MOSI), and the
+    /// CS pin to a push-pull output for software NSS or PA4 to AF5 (NSS)
+    /// for hardware NSS.  With the default PA4 CS that touches MODER[15:8],
+    /// OTYPER[4] and AFRL[31:20] (plus AFRL[19:16] for hardware NSS); every
+    /// other pin is left alone.  GPIOA must be clocked.
+    pub fn configure_gpio(cfg: &Stm32SpiConfig) {
+        unsafe {
+            if cfg.nss == Nss::Software {
+                // Latch CS idle before the pin becomes an output, so
+                // switching the mode can't glitch it active.
+                Self::cs_deassert(cfg.cs_pin, cfg.cs_polarity);
+                wr(GPIOA_OTYPER, gpio_otyper(rd(GPIOA_OTYPER), cfg.cs_pin));
             }
+            wr(GPIOA_AFRL, gpio_afrl(rd(GPIOA_AFRL), cfg.nss));
+            wr(GPIOA_MODER, gpio_moder(rd(GPIOA_MODER), cfg.nss, cfg.cs_pin));
+        }
+    }
 
-            Self::cs_high();
+    /// Configure SPI1 as `cfg` describes.  See `init()`.
+    ///
+    /// Fails with `ModeFault` if enabling the master already faulted, i.e.
+    /// NSS is being held low.
+    pub fn init_with(cfg: Stm32SpiConfig) -> Result<Self, Stm32SpiError> {
+        #[cfg(feature = "real-hardware")]
+        {
+            Self::enable_clocks();
+            Self::configure_gpio(&cfg);
         }
-        Ok(())
+
+        let (cr1, _) = control_words(&cfg);
+
+        unsafe {
+            // CRCPR, CR1, CR2, all with SPE=0 (many F4 errata require
+            // config while the peripheral is disabled).  CR2 only has SSOE,
+            // for hardware NSS; its interrupt enables are `irq`'s.
+            for (reg, val) in config_writes(&cfg) {
+                wr(reg, val);
+            }
+
+            if cfg.nss == Nss::Software {
+                // Now enable
+                wr(SPI1_CR1, cr1 | CR1_SPE);
+
+                // Deassert CS to start clean
+                Self::cs_deassert(cfg.cs_pin, cfg.cs_polarity);
+
+                check_sr(rd(SPI1_SR))?;
+            }
+        }
+
+        Ok(Self {
+            nss: cfg.nss,
+            bidi: cfg.bidi,
+            frame_16bit: cfg.frame_16bit,
+            cs_pin: cfg.cs_pin,
+            cs_polarity: cfg.cs_polarity,
+            crc: cfg.crc_poly.is_some(),
+            delay: SpinDelay::default(),
+            sent: false,
+        })
     }
-}
\ No newline at end of file
+}
+
+impl<D> Stm32Spi1Device<D> {
+    /// Honour `Operation::DelayNs` with `delay` instead of `SpinDelay`,
+    /// e.g. a timer-backed delay or one calibrated for a different clock.
+    pub fn with_delay<D2: DelayNs>(self, delay: D2) -> Stm32Spi1Device<D2> {
+        Stm32Spi1Device {
+            nss: self.nss,
+            bidi: self.bidi,
+            frame_16bit: self.frame_16bit,
+            cs_pin: self.cs_pin,
+            cs_polarity: self.cs_polarity,
+            crc: self.crc,
+            delay,
+            sent: self.sent,
+        }
+    }
+
+    /// Point the bidirectional data line at `dir`.  The previous phase must
+    /// finish shifting first, and anything it left in DR is stale.
+    #[inline(always)]
+    unsafe fn set_direction(dir: Direction) {
+        unsafe {
+            while rd(SPI1_SR) & SR_BSY != 0 {}
+            while rd(SPI1_SR) & SR_RXNE != 0 {
+                rd_byte(SPI1_DR);
+            }
+            wr(SPI1_CR1, with_direction(rd(SPI1_CR1), dir));
+        }
+    }
+
+    // -- Status introspection -----------------------------------------------
+
+    /// Raw SR, for working out why a transfer stalled: TXE never set means
+    /// the shifter is stuck, RXNE never set means nothing came back.
+    pub fn status(&self) -> u32 {
+        unsafe { rd(SPI1_SR) }
+    }
+
+    pub fn is_txe(&self) -> bool {
+        self.status() & SR_TXE != 0
+    }
+
+    pub fn is_rxne(&self) -> bool {
+        self.status() & SR_RXNE != 0
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.status() & SR_BSY != 0
+    }
+
+    /// OVR: a frame arrived before the previous one was read from DR.
+    pub fn is_overrun(&self) -> bool {
+        self.status() & SR_OVR != 0
+    }
+
+    /// Wait until everything written has been clocked out: TXE set and BSY
+    /// clear.  Transactions already do this before releasing CS; call it
+    /// after `try_transfer_byte` before changing CS or the configuration.
+    pub fn flush(&mut self) {
+        unsafe { drain(|| rd(SPI1_SR)) }
+    }
+
+    // -- CS control via GPIOA BSRR -------------------------------------------
+
+    /// Drive CS to its selected level – low unless `polarity` is active-high.
+    #[inline(always)]
+    unsafe fn cs_assert(pin: u8, polarity: CsPolarity) {
+        unsafe { wr(GPIOA_BSRR, cs_bsrr(pin, polarity, true)) }
+    }
+
+    /// Drive CS to its idle level.
+    #[inline(always)]
+    unsafe fn cs_deassert(pin: u8, polarity: CsPolarity) {
+        unsafe { wr(GPIOA_BSRR, cs_bsrr(pin, polarity, false)) }
+    }
+
+    // -- Core transfer -------------------------------------------------------
+
+    /// One `exchange_step` against SPI1: write `tx` once TXE is set, then
+    /// read the reply once RXNE is.  DR is accessed at the frame's width.
+    #[inline(always)]
+    unsafe fn step_frame<W: SpiWord>(sent: &mut bool, tx: W) -> nb::Result<W, Stm32SpiError> {
+        unsafe { exchange_step(sent, rd(SPI1_SR), || tx.write_dr(), || W::read_dr(), || clear_modf()) }
+    }
+
+    /// Full-duplex single-frame exchange, blocking: `exchange` over SPI1's
+    /// volatile SR and DR.  `crc_next` sets CRCNEXT straight after the
+    /// write, so the CRC follows this frame.
+    #[inline(always)]
+    unsafe fn transfer_word<W: SpiWord>(tx: W, crc_next: bool) -> Result<W, Stm32SpiError> {
+        let write = || unsafe {
+            tx.write_dr();
+            if crc_next {
+                wr(SPI1_CR1, rd(SPI1_CR1) | CR1_CRCNEXT);
+            }
+        };
+        unsafe { exchange(|| rd(SPI1_SR), write, || W::read_dr(), || clear_modf()) }
+    }
+
+    /// Restart the CRC for a new transaction: CRCEN can only be toggled
+    /// with SPE clear, and clearing it resets both CRC registers.
+    #[inline(always)]
+    unsafe fn reset_crc() {
+        unsafe {
+            let cr1 = rd(SPI1_CR1);
+            wr(SPI1_CR1, cr1 & !(CR1_SPE | CR1_CRCEN));
+            wr(SPI1_CR1, cr1 & !CR1_SPE);
+            wr(SPI1_CR1, cr1);
+        }
+    }
+
+    /// Collect the CRC frame clocked in after CRCNEXT, then check and clear
+    /// CRCERR.
+    unsafe fn finish_crc<W: SpiWord>() -> Result<(), Stm32SpiError> {
+        unsafe {
+            // The CRC went out on its own: only the RXNE half is left.
+            let mut sent = true;
+            nb::block!(exchange_step(&mut sent, rd(SPI1_SR), || {}, || W::read_dr(), || clear_modf()))?;
+
+            let status = crc_status(rd(SPI1_SR));
+            if status.is_err() {
+                // CRCERR is rc_w0; the other SR bits ignore writes.
+                wr(SPI1_SR, !SR_CRCERR);
+            }
+            status
+        }
+    }
+
+    /// Non-blocking single-byte exchange for a superloop or cooperative
+    /// scheduler: `WouldBlock` until SPI1 has taken `tx` and clocked a
+    /// byte back, then the received byte.  Call it again with the same
+    /// `tx` until it stops blocking – `tx` is only written once.
+    ///
+    /// CS is left alone: select the device around the exchange yourself
+    /// (or use `Nss::Hardware`).  Don't interleave with `transaction`
+    /// while an exchange is half done.  `FrameSize` on a 16-bit device.
+    pub fn try_transfer_byte(&mut self, tx: u8) -> nb::Result<u8, Stm32SpiError> {
+        if self.frame_16bit {
+            return Err(nb::Error::Other(Stm32SpiError::FrameSize));
+        }
+        unsafe { Self::step_frame(&mut self.sent, tx) }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// SpiDevice impl
+// ---------------------------------------------------------------------------
+
+impl<D> embedded_hal::spi::ErrorType for Stm32Spi1Device<D> {
+    type Error = Stm32SpiError;
+}
+
+impl<D: DelayNs> SpiDevice<u8> for Stm32Spi1Device<D> {
+    fn transaction(
+        &mut self,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), Stm32SpiError> {
+        self.transact(operations)
+    }
+}
+
+impl<D: DelayNs> SpiDevice<u16> for Stm32Spi1Device<D> {
+    fn transaction(
+        &mut self,
+        operations: &mut [Operation<'_, u16>],
+    ) -> Result<(), Stm32SpiError> {
+        self.transact(operations)
+    }
+}
+
+impl<D: DelayNs> Stm32Spi1Device<D> {
+    /// Wiring check that needs no mock: with MOSI (PA7) tied to MISO (PA6),
+    /// clock `pattern` out with `TransferInPlace` and report whether every
+    /// byte came back unchanged.  `Ok(false)` means SPI1 runs but the data
+    /// path is broken; an error means SPI1 itself failed.  With the mock
+    /// attached instead of a loopback this is expected to return `false`.
+    pub fn loopback_test(&mut self, pattern: &[u8]) -> Result<bool, Stm32SpiError> {
+        let mut buf = [0u8; 16];
+
+        for chunk in pattern.chunks(buf.len()) {
+            let wire = &mut buf[..chunk.len()];
+            wire.copy_from_slice(chunk);
+            SpiDevice::<u8>::transfer_in_place(self, wire)?;
+            if wire != chunk {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// `transaction` for either frame width.
+    fn transact<W: SpiWord>(&mut self, operations: &mut [Operation<'_, W>]) -> Result<(), Stm32SpiError> {
+        // Nothing to clock: don't pulse CS, which the mock would count as
+        // a (bogus) transaction.
+        if operations.is_empty() {
+            return Ok(());
+        }
+        self.validate(operations)?;
+
+        unsafe {
+            if self.crc {
+                Self::reset_crc();
+            }
+            // Deselects on drop – after `run` returns, `?` included.
+            let _cs = CsGuard::select(self.nss, self.cs_pin, self.cs_polarity);
+            self.run(operations)
+        }
+    }
+
+    /// Reject `operations` this configuration can't run, before any CS edge.
+    fn validate<W: SpiWord>(&self, operations: &[Operation<'_, W>]) -> Result<(), Stm32SpiError> {
+        if W::SIXTEEN_BIT != self.frame_16bit {
+            return Err(Stm32SpiError::FrameSize);
+        }
+        if self.bidi {
+            for op in operations.iter() {
+                bidi_direction(op)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The body of `transaction`, while the `CsGuard` holds CS asserted.
+    unsafe fn run<W: SpiWord>(&mut self, operations: &mut [Operation<'_, W>]) -> Result<(), Stm32SpiError> {
+        // Frames still to clock when the CRC is on, to set CRCNEXT after
+        // the last one; zero (never "last") without it.
+        let total = if self.crc { operations.iter().map(frames).sum() } else { 0 };
+        let mut left = total;
+
+        unsafe {
+            for op in operations.iter_mut() {
+                // DMA can't set CRCNEXT mid-stream, so CRC runs stay on the CPU.
+                #[cfg(feature = "dma")]
+                if !self.bidi
+                    && !self.crc
+                    && let Some(result) = W::try_dma(op)
+                {
+                    result?;
+                    continue;
+                }
+
+                if self.bidi
+                    && let Ok(Some(dir)) = bidi_direction(op)
+                {
+                    Self::set_direction(dir);
+                }
+
+                match op {
+                    Operation::Write(buf) => {
+                        for &w in buf.iter() {
+                            Self::transfer_word(w, last_frame(&mut left))?; // discard RX
+                        }
+                    }
+                    Operation::Read(buf) => {
+                        for slot in buf.iter_mut() {
+                            *slot = Self::transfer_word(W::DUMMY, last_frame(&mut left))?; // dummy TX
+                        }
+                    }
+                    Operation::Transfer(rx, tx) => {
+                        // True simultaneous full-duplex, for the longer
+                        // slice: dummy frames once `tx` runs out, RX
+                        // dropped once `rx` does.
+                        for i in 0..rx.len().max(tx.len()) {
+                            let t = tx.get(i).copied().unwrap_or(W::DUMMY);
+                            let r = Self::transfer_word(t, last_frame(&mut left))?;
+                            if let Some(slot) = rx.get_mut(i) {
+                                *slot = r;
+                            }
+                        }
+                    }
+                    Operation::TransferInPlace(buf) => {
+                        for slot in buf.iter_mut() {
+                            *slot = Self::transfer_word(*slot, last_frame(&mut left))?;
+                        }
+                    }
+                    Operation::DelayNs(ns) => {
+                        // Renode doesn't need the gap, but real devices
+                        // (and timed models) rely on it.
+                        self.delay.delay_ns(*ns);
+                    }
+                }
+            }
+
+            if total > 0 {
+                Self::finish_crc::<W>()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// MultiCsDevice – several slaves sharing SPI1
+// ---------------------------------------------------------------------------
+
+/// A software chip-select line: any GPIO port's BSRR and a pin on it, and
+/// the level that selects its slave.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CsPin {
+    pub bsrr: u32,
+    pub pin: u8,
+    pub polarity: CsPolarity,
+}
+
+impl CsPin {
+    /// An active-low line on GPIOA.
+    pub const fn gpioa(pin: u8) -> Self {
+        Self { bsrr: GPIOA_BSRR, pin, polarity: CsPolarity::ActiveLow }
+    }
+
+    pub const fn with_polarity(mut self, polarity: CsPolarity) -> Self {
+        self.polarity = polarity;
+        self
+    }
+
+    /// The BSRR word driving this pin to its `asserted` or idle level.
+    const fn bsrr_word(&self, asserted: bool) -> u32 {
+        cs_bsrr(self.pin, self.polarity, asserted)
+    }
+}
+
+/// `(BSRR address, word)` writes that leave only `cs[selected]` asserted,
+/// deasserting the others first so two slaves never drive MISO at once.
+fn select_writes(cs: &[CsPin], selected: usize) -> impl Iterator<Item = (u32, u32)> + '_ {
+    let others = cs.iter().enumerate().filter(move |&(i, _)| i != selected);
+    others
+        .map(|(_, p)| (p.bsrr, p.bsrr_word(false)))
+        .chain(cs.get(selected).map(|p| (p.bsrr, p.bsrr_word(true))))
+}
+
+/// Deasserts one `CsPin` when dropped, once the last frame has shifted
+/// out, like `CsGuard`.
+struct PinGuard(CsPin);
+
+impl Drop for PinGuard {
+    fn drop(&mut self) {
+        unsafe {
+            drain(|| rd(SPI1_SR));
+            wr(self.0.bsrr, self.0.bsrr_word(false));
+        }
+    }
+}
+
+/// SPI1 shared by `N` slaves, each on its own software CS line.  A
+/// transaction asserts only the line picked by `select`.
+///
+/// The CS pins must already be push-pull outputs on clocked ports; this
+/// only drives them.  The wrapped device must use software NSS – its own
+/// `cs_pin` is left deasserted.
+pub struct MultiCsDevice<const N: usize, D = SpinDelay> {
+    spi: Stm32Spi1Device<D>,
+    cs: [CsPin; N],
+    selected: usize,
+}
+
+impl<const N: usize, D: DelayNs> MultiCsDevice<N, D> {
+    /// Take over `spi` with `cs` as the slave-select lines, all deasserted,
+    /// and slave 0 selected for the next transaction.
+    ///
+    /// Fails with `HardwareNss`, before touching any pin, if `spi` doesn't
+    /// use software NSS.
+    pub fn new(spi: Stm32Spi1Device<D>, cs: [CsPin; N]) -> Result<Self, Stm32SpiError> {
+        if spi.nss != Nss::Software {
+            return Err(Stm32SpiError::HardwareNss);
+        }
+        for p in &cs {
+            unsafe { wr(p.bsrr, p.bsrr_word(false)) }
+        }
+        Ok(Self { spi, cs, selected: 0 })
+    }
+
+    /// Address slave `idx` (an index into the `cs` array) from now on.
+    /// Fails with `NoSuchCs`, keeping the current slave, if `idx` is out
+    /// of range.
+    pub fn select(&mut self, idx: usize) -> Result<(), Stm32SpiError> {
+        if idx >= N {
+            return Err(Stm32SpiError::NoSuchCs);
+        }
+        self.selected = idx;
+        Ok(())
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn into_inner(self) -> Stm32Spi1Device<D> {
+        self.spi
+    }
+}
+
+impl<const N: usize, D> embedded_hal::spi::ErrorType for MultiCsDevice<N, D> {
+    type Error = Stm32SpiError;
+}
+
+impl<const N: usize, D: DelayNs> SpiDevice<u8> for MultiCsDevice<N, D> {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Stm32SpiError> {
+        if operations.is_empty() {
+            return Ok(());
+        }
+        self.spi.validate(operations)?;
+
+        unsafe {
+            // Each transaction gets its own CRC, whichever slave it's for.
+            if self.spi.crc {
+                Stm32Spi1Device::<D>::reset_crc();
+            }
+            for (bsrr, word) in select_writes(&self.cs, self.selected) {
+                wr(bsrr, word);
+            }
+            let _cs = PinGuard(self.cs[self.selected]);
+            self.spi.run(operations)
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Interrupt-driven path
+// ---------------------------------------------------------------------------
+
+/// SPI1 driven from its interrupt instead of a polling loop.
+///
+/// `start_transaction` selects the slave, queues the bytes and enables
+/// TXEIE/RXNEIE; `on_spi1_interrupt`, called from the SPI1 vector, moves
+/// one byte per TXE/RXNE pair and sets a done flag after the last one;
+/// `take_result` then releases CS and hands back what came in.  One frame
+/// is in flight at a time, so RX can't overrun.
+///
+/// The transfer lives in a static owned by the ISR while `ACTIVE` is set
+/// and by the caller otherwise, so only one can be pending.
+pub mod irq {
+    use super::*;
+
+    use core::cell::UnsafeCell;
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    /// Bytes one interrupt-driven transfer can carry.
+    pub const IRQ_BUF: usize = 64;
+
+    // CR2 bits.  Only this module sets them.
+    pub(super) const CR2_RXNEIE: u32 = 1 << 6;
+    pub(super) const CR2_TXEIE:  u32 = 1 << 7;
+
+    /// NVIC set-enable for IRQs 32..63; SPI1 is IRQ 35.
+    const NVIC_ISER1:   u32 = 0xE000_E104;
+    const SPI1_IRQ_BIT: u32 = 1 << (35 - 32);
+
+    /// One transfer, exchanged in place in `buf`.
+    pub(super) struct IrqTransfer<const N: usize> {
+        buf: [u8; N],
+        len: usize,
+        sent: usize,
+        received: usize,
+        result: Option<Result<(), Stm32SpiError>>,
+    }
+
+    impl<const N: usize> IrqTransfer<N> {
+        pub(super) const fn new() -> Self {
+            Self { buf: [0; N], len: 0, sent: 0, received: 0, result: None }
+        }
+
+        /// Queue `tx`; an empty one is finished at once.
+        pub(super) fn load(&mut self, tx: &[u8]) -> Result<(), Stm32SpiError> {
+            if tx.len() > N {
+                return Err(Stm32SpiError::TooLong);
+            }
+            self.buf[..tx.len()].copy_from_slice(tx);
+            self.len = tx.len();
+            self.sent = 0;
+            self.received = 0;
+            self.result = if tx.is_empty() { Some(Ok(())) } else { None };
+            Ok(())
+        }
+
+        /// Handle one interrupt with status `sr`: collect a byte on RXNE,
+        /// send the next on TXE once nothing is in flight.  Returns the CR2
+        /// interrupt enables still wanted – 0 once `result` is set.
+        pub(super) fn advance(
+            &mut self,
+            sr: u32,
+            read_dr: impl FnOnce() -> u8,
+            write_dr: impl FnOnce(u8),
+        ) -> u32 {
+            if self.result.is_some() {
+                return 0;
+            }
+            let in_flight = self.sent > self.received;
+
+            if in_flight && sr & SR_RXNE != 0 {
+                self.buf[self.received] = read_dr();
+                self.received += 1;
+                if self.received == self.len {
+                    self.result = Some(Ok(()));
+                    return 0;
+                }
+            } else if !in_flight && sr & SR_TXE != 0 {
+                write_dr(self.buf[self.sent]);
+                self.sent += 1;
+            } else if let Err(e) = sr_fault(sr) {
+                self.result = Some(Err(e));
+                return 0;
+            }
+
+            // TXE stays set while a frame shifts, so only ask for it when
+            // the next byte can go.
+            if self.sent > self.received { CR2_RXNEIE } else { CR2_TXEIE | CR2_RXNEIE }
+        }
+
+        pub(super) fn result(&self) -> Option<Result<(), Stm32SpiError>> {
+            self.result
+        }
+
+        pub(super) fn received(&self) -> &[u8] {
+            &self.buf[..self.received]
+        }
+    }
+
+    struct Slot(UnsafeCell<IrqTransfer<IRQ_BUF>>);
+
+    // Access is handed back and forth through `ACTIVE`/`DONE`, never shared.
+    unsafe impl Sync for Slot {}
+
+    static SLOT: Slot = Slot(UnsafeCell::new(IrqTransfer::new()));
+    /// The ISR owns `SLOT`.
+    static ACTIVE: AtomicBool = AtomicBool::new(false);
+    /// Finished and waiting for `take_result`.
+    static DONE: AtomicBool = AtomicBool::new(false);
+
+    /// The SPI1 interrupt handler: advance the pending transfer, if any.
+    /// Call it from the SPI1 vector (IRQ 35).
+    pub fn on_spi1_interrupt() {
+        #[cfg(feature = "interrupt")]
+        if stream::on_interrupt() {
+            return;
+        }
+        if !ACTIVE.load(Ordering::Acquire) {
+            // Nothing to move, so nothing should have enabled the
+            // interrupt: returning with RXNE still set would take it again
+            // at once.  Drain DR and mask both enables.
+            unsafe {
+                if rd(SPI1_SR) & SR_RXNE != 0 {
+                    u8::read_dr();
+                }
+                wr(SPI1_CR2, rd(SPI1_CR2) & !(CR2_TXEIE | CR2_RXNEIE));
+            }
+            return;
+        }
+        unsafe {
+            let transfer = &mut *SLOT.0.get();
+            let enables = transfer.advance(rd(SPI1_SR), || u8::read_dr(), |b| b.write_dr());
+
+            wr(SPI1_CR2, (rd(SPI1_CR2) & !(CR2_TXEIE | CR2_RXNEIE)) | enables);
+            if transfer.result().is_some() {
+                if transfer.result() != Some(Ok(())) {
+                    clear_modf();
+                }
+                ACTIVE.store(false, Ordering::Release);
+                DONE.store(true, Ordering::Release);
+            }
+        }
+    }
+
+    impl<D> Stm32Spi1Device<D> {
+        /// Get ready for `start_transaction`: clear TXEIE/RXNEIE so polled
+        /// transactions don't raise the interrupt, and unmask SPI1 in the
+        /// NVIC.
+        pub fn init_interrupts(&mut self) {
+            unsafe {
+                wr(SPI1_CR2, rd(SPI1_CR2) & !(CR2_TXEIE | CR2_RXNEIE));
+                wr(NVIC_ISER1, SPI1_IRQ_BIT);
+            }
+        }
+
+        /// Select the slave and start exchanging `tx` in the background.
+        /// Poll `is_done`, then collect with `take_result`.  8-bit frames
+        /// only; don't run polled transactions until it's collected.
+        pub fn start_transaction(&mut self, tx: &[u8]) -> Result<(), Stm32SpiError> {
+            if self.frame_16bit {
+                return Err(Stm32SpiError::FrameSize);
+            }
+            if ACTIVE.load(Ordering::Acquire) || DONE.load(Ordering::Acquire) {
+                return Err(Stm32SpiError::Busy);
+            }
+
+            let transfer = unsafe { &mut *SLOT.0.get() };
+            transfer.load(tx)?;
+            if tx.is_empty() {
+                DONE.store(true, Ordering::Release);
+                return Ok(());
+            }
+
+            unsafe {
+                // Released by `take_result`.
+                core::mem::forget(CsGuard::select(self.nss, self.cs_pin, self.cs_polarity));
+                ACTIVE.store(true, Ordering::Release);
+                wr(SPI1_CR2, rd(SPI1_CR2) | CR2_TXEIE | CR2_RXNEIE);
+            }
+            Ok(())
+        }
+
+        /// The pending transfer has finished (or faulted).
+        pub fn is_done(&self) -> bool {
+            DONE.load(Ordering::Acquire)
+        }
+
+        /// Once `is_done`, release CS and copy the received bytes into
+        /// `rx` (as many as fit), returning how many came in.  `None`
+        /// while the transfer is still running or if none was started.
+        pub fn take_result(&mut self, rx: &mut [u8]) -> Option<Result<usize, Stm32SpiError>> {
+            if !DONE.load(Ordering::Acquire) {
+                return None;
+            }
+
+            let transfer = unsafe { &*SLOT.0.get() };
+            let received = transfer.received();
+            if transfer.len > 0 {
+                drop(CsGuard { nss: self.nss, cs_pin: self.cs_pin, cs_polarity: self.cs_polarity });
+            }
+            let n = received.len().min(rx.len());
+            rx[..n].copy_from_slice(&received[..n]);
+            let result = transfer.result().map(|r| r.map(|()| received.len()));
+
+            DONE.store(false, Ordering::Release);
+            result
+        }
+    }
+
+    /// Streaming RX (`interrupt` feature): the SPI1 interrupt moves every
+    /// received byte into a `heapless::spsc` queue while the main loop
+    /// keeps DR fed, so TX never waits on the previous byte's RX the way
+    /// the lockstep `transfer_word` does.  For throughput runs that push
+    /// the mock's back-pressure.
+    #[cfg(feature = "interrupt")]
+    pub mod stream {
+        use super::*;
+
+        use heapless::spsc::{Consumer, Producer, Queue};
+
+        /// Queue slots; one is always kept free, so `RX_QUEUE - 1` bytes can
+        /// wait for the main loop.
+        pub const RX_QUEUE: usize = 64;
+
+        struct RxQueue(UnsafeCell<Queue<u8, RX_QUEUE>>);
+        struct ProducerSlot(UnsafeCell<Option<Producer<'static, u8, RX_QUEUE>>>);
+
+        // The queue is split once per transaction; the ISR only touches the
+        // producer while `STREAMING` is set, the main loop only otherwise.
+        unsafe impl Sync for RxQueue {}
+        unsafe impl Sync for ProducerSlot {}
+
+        static QUEUE: RxQueue = RxQueue(UnsafeCell::new(Queue::new()));
+        static PRODUCER: ProducerSlot = ProducerSlot(UnsafeCell::new(None));
+        /// The ISR owns `PRODUCER`.
+        static STREAMING: AtomicBool = AtomicBool::new(false);
+        /// The ISR dropped a byte into a full queue.
+        static OVERFLOW: AtomicBool = AtomicBool::new(false);
+
+        /// ISR half: on RXNE, read DR into `producer`.  A full queue drops
+        /// the byte (DR must still be read to clear RXNE) and reports
+        /// `RxOverflow`.
+        pub(in super::super) fn queue_rx(
+            sr: u32,
+            read_dr: impl FnOnce() -> u8,
+            producer: &mut Producer<'_, u8, RX_QUEUE>,
+        ) -> Result<(), Stm32SpiError> {
+            if sr & SR_RXNE == 0 {
+                return Ok(());
+            }
+            producer.enqueue(read_dr()).map_err(|_| Stm32SpiError::RxOverflow)
+        }
+
+        /// Main-loop half, exchanging `buf` in place: drain the queue into
+        /// `buf`, then write the next byte if TXE allows.
+        pub(in super::super) struct Feed<'a> {
+            buf: &'a mut [u8],
+            sent: usize,
+            received: usize,
+        }
+
+        impl<'a> Feed<'a> {
+            pub(in super::super) fn new(buf: &'a mut [u8]) -> Self {
+                Self { buf, sent: 0, received: 0 }
+            }
+
+            /// One pass with status `sr`.  `Ok(true)` once every byte sent
+            /// has come back.
+            pub(in super::super) fn step(
+                &mut self,
+                sr: u32,
+                write_dr: impl FnOnce(u8),
+                consumer: &mut Consumer<'_, u8, RX_QUEUE>,
+            ) -> Result<bool, Stm32SpiError> {
+                while let Some(b) = consumer.dequeue() {
+                    // More bytes than were sent is a stray RXNE; drop it.
+                    if self.received < self.sent {
+                        self.buf[self.received] = b;
+                        self.received += 1;
+                    }
+                }
+                if self.received == self.buf.len() {
+                    return Ok(true);
+                }
+
+                if self.sent < self.buf.len() && sr & SR_TXE != 0 {
+                    write_dr(self.buf[self.sent]);
+                    self.sent += 1;
+                } else {
+                    sr_fault(sr)?;
+                }
+                Ok(false)
+            }
+        }
+
+        /// Called first from `on_spi1_interrupt`; `true` if a streaming
+        /// transaction took the interrupt.
+        pub(super) fn on_interrupt() -> bool {
+            if !STREAMING.load(Ordering::Acquire) {
+                return false;
+            }
+            unsafe {
+                if let Some(producer) = (*PRODUCER.0.get()).as_mut()
+                    && queue_rx(rd(SPI1_SR), || u8::read_dr(), producer).is_err()
+                {
+                    OVERFLOW.store(true, Ordering::Release);
+                }
+            }
+            true
+        }
+
+        impl<D> Stm32Spi1Device<D> {
+            /// Exchange `buf` in place with RX taken by the SPI1 interrupt
+            /// (RXNEIE) while this loop writes DR as fast as TXE allows.
+            /// Blocks until every byte is back.  Needs `init_interrupts`
+            /// first, 8-bit frames, and no `start_transaction` pending.
+            ///
+            /// Fails with `RxOverflow` if the queue filled up, and with
+            /// `ModeFault` as the polled paths do.
+            pub fn transaction_irq(&mut self, buf: &mut [u8]) -> Result<(), Stm32SpiError> {
+                if self.frame_16bit {
+                    return Err(Stm32SpiError::FrameSize);
+                }
+                if ACTIVE.load(Ordering::Acquire) || DONE.load(Ordering::Acquire) {
+                    return Err(Stm32SpiError::Busy);
+                }
+                if buf.is_empty() {
+                    return Ok(());
+                }
+
+                unsafe {
+                    let queue = &mut *QUEUE.0.get();
+                    *queue = Queue::new();
+                    let (producer, mut consumer) = queue.split();
+                    *PRODUCER.0.get() = Some(producer);
+                    OVERFLOW.store(false, Ordering::Relaxed);
+                    STREAMING.store(true, Ordering::Release);
+
+                    let _cs = CsGuard::select(self.nss, self.cs_pin, self.cs_polarity);
+                    wr(SPI1_CR2, rd(SPI1_CR2) | CR2_RXNEIE);
+
+                    let mut feed = Feed::new(buf);
+                    let result = loop {
+                        if OVERFLOW.load(Ordering::Acquire) {
+                            break Err(Stm32SpiError::RxOverflow);
+                        }
+                        match feed.step(rd(SPI1_SR), |b| b.write_dr(), &mut consumer) {
+                            Ok(true) => break Ok(()),
+                            Ok(false) => {}
+                            Err(e) => {
+                                clear_modf();
+                                break Err(e);
+                            }
+                        }
+                    };
+
+                    wr(SPI1_CR2, rd(SPI1_CR2) & !CR2_RXNEIE);
+                    STREAMING.store(false, Ordering::Release);
+                    *PRODUCER.0.get() = None;
+                    result
+                }
+            }
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// DMA path (feature = "dma")
+// ---------------------------------------------------------------------------
+
+/// SPI1 over DMA2.  SPI1_RX is stream 0 / channel 3, SPI1_TX is stream 3 /
+/// channel 3.  DMA2 must be clocked (RCC AHB1ENR bit 22): `enable_clocks`
+/// does that along with the SPI clocks, and like them Renode doesn't need it.
+#[cfg(feature = "dma")]
+mod dma {
+    use super::*;
+
+    use core::sync::atomic::{Ordering, compiler_fence};
+
+    const DMA2_BASE:  u32 = 0x4002_6400;
+    const DMA2_LISR:  u32 = DMA2_BASE;
+    const DMA2_LIFCR: u32 = DMA2_BASE + 0x08;
+
+    const RX_STREAM: u32 = 0;
+    const TX_STREAM: u32 = 3;
+
+    // Stream registers: SxCR, then NDTR / PAR / M0AR at +4 / +8 / +C
+    const fn sxcr(n: u32) -> u32 {
+        DMA2_BASE + 0x10 + 0x18 * n
+    }
+
+    const fn sxndtr(n: u32) -> u32 {
+        sxcr(n) + 0x04
+    }
+
+    const fn sxpar(n: u32) -> u32 {
+        sxcr(n) + 0x08
+    }
+
+    const fn sxm0ar(n: u32) -> u32 {
+        sxcr(n) + 0x0C
+    }
+
+    // SxCR bits
+    const SXCR_EN:      u32 = 1 << 0;
+    const SXCR_DIR_M2P: u32 = 0b01 << 6;  // 0b00 = peripheral-to-memory
+    const SXCR_MINC:    u32 = 1 << 10;
+    const SXCR_CHSEL_3: u32 = 3 << 25;
+
+    // LISR / LIFCR – stream 0 flags live in bits 0..=5, stream 3 in 22..=27
+    const RX_TEIF:  u32 = 1 << 3;
+    const RX_TCIF:  u32 = 1 << 5;
+    const RX_FLAGS: u32 = 0x3D;           // FEIF, DMEIF, TEIF, HTIF, TCIF
+    const TX_TEIF:  u32 = 1 << 25;
+    const TX_FLAGS: u32 = 0x3D << 22;
+
+    // CR2 bits
+    const CR2_RXDMAEN: u32 = 1 << 0;
+    const CR2_TXDMAEN: u32 = 1 << 1;
+
+    /// Below this many bytes, programming two streams costs more than the
+    /// PIO loop.
+    pub(super) const THRESHOLD: usize = 16;
+
+    /// NDTR is 16 bits wide; longer operations go in chunks.
+    const MAX_CHUNK: usize = u16::MAX as usize;
+
+    /// Whether `op` should go through `run` rather than the PIO loop.
+    /// Uneven `Transfer`s stay on the PIO loop, which pads the short side.
+    pub(super) fn eligible(op: &Operation<'_, u8>) -> bool {
+        match op {
+            Operation::Write(buf) => buf.len() >= THRESHOLD,
+            Operation::Read(buf) => buf.len() >= THRESHOLD,
+            Operation::Transfer(rx, tx) => rx.len() == tx.len() && rx.len() >= THRESHOLD,
+            Operation::TransferInPlace(buf) => buf.len() >= THRESHOLD,
+            Operation::DelayNs(_) => false,
+        }
+    }
+
+    /// SxCR for one direction, stepping the memory address if `minc`.
+    const fn stream_cr(m2p: bool, minc: bool) -> u32 {
+        let mut cr = SXCR_CHSEL_3;
+        if m2p {
+            cr |= SXCR_DIR_M2P;
+        }
+        if minc {
+            cr |= SXCR_MINC;
+        }
+        cr
+    }
+
+    /// Move one eligible operation.  Writes discard RX into a dummy byte
+    /// and reads clock out zeros, both with MINC clear.
+    pub(super) unsafe fn run(op: &mut Operation<'_, u8>) -> Result<(), Stm32SpiError> {
+        let mut sink = 0u8;
+        let zero = 0u8;
+
+        unsafe {
+            match op {
+                Operation::Write(buf) => {
+                    for chunk in buf.chunks(MAX_CHUNK) {
+                        transfer(chunk.as_ptr(), true, &mut sink, false, chunk.len())?;
+                    }
+                }
+                Operation::Read(buf) => {
+                    for chunk in buf.chunks_mut(MAX_CHUNK) {
+                        transfer(&zero, false, chunk.as_mut_ptr(), true, chunk.len())?;
+                    }
+                }
+                Operation::Transfer(rx, tx) => {
+                    for (r, t) in rx.chunks_mut(MAX_CHUNK).zip(tx.chunks(MAX_CHUNK)) {
+                        transfer(t.as_ptr(), true, r.as_mut_ptr(), true, r.len())?;
+                    }
+                }
+                Operation::TransferInPlace(buf) => {
+                    for chunk in buf.chunks_mut(MAX_CHUNK) {
+                        // TX reads each byte before RX overwrites it.
+                        let p = chunk.as_mut_ptr();
+                        transfer(p, true, p, true, chunk.len())?;
+                    }
+                }
+                Operation::DelayNs(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Full-duplex DMA exchange of `len` (<= `MAX_CHUNK`) bytes.  Done when
+    /// the RX stream completes – the last byte in is after the last out.
+    unsafe fn transfer(
+        tx: *const u8,
+        tx_inc: bool,
+        rx: *mut u8,
+        rx_inc: bool,
+        len: usize,
+    ) -> Result<(), Stm32SpiError> {
+        unsafe {
+            // A stale byte in DR would be the first thing the RX stream takes.
+            while rd(SPI1_SR) & SR_RXNE != 0 {
+                rd_byte(SPI1_DR);
+            }
+            wr(DMA2_LIFCR, RX_FLAGS | TX_FLAGS);
+
+            wr(sxpar(RX_STREAM), SPI1_DR);
+            wr(sxm0ar(RX_STREAM), rx as u32);
+            wr(sxndtr(RX_STREAM), len as u32);
+            wr(sxcr(RX_STREAM), stream_cr(false, rx_inc));
+
+            wr(sxpar(TX_STREAM), SPI1_DR);
+            wr(sxm0ar(TX_STREAM), tx as u32);
+            wr(sxndtr(TX_STREAM), len as u32);
+            wr(sxcr(TX_STREAM), stream_cr(true, tx_inc));
+
+            // The buffers must be in memory before the DMA reads them.
+            compiler_fence(Ordering::Release);
+
+            // RM0090 order: RXDMAEN, then the streams, then TXDMAEN.
+            wr(SPI1_CR2, rd(SPI1_CR2) | CR2_RXDMAEN);
+            wr(sxcr(RX_STREAM), stream_cr(false, rx_inc) | SXCR_EN);
+            wr(sxcr(TX_STREAM), stream_cr(true, tx_inc) | SXCR_EN);
+            wr(SPI1_CR2, rd(SPI1_CR2) | CR2_TXDMAEN);
+
+            let result = loop {
+                let isr = rd(DMA2_LISR);
+                if isr & (RX_TEIF | TX_TEIF) != 0 {
+                    break Err(Stm32SpiError::Dma);
+                }
+                if isr & RX_TCIF != 0 {
+                    break Ok(());
+                }
+                if let Err(e) = check_sr(rd(SPI1_SR)) {
+                    break Err(e);
+                }
+            };
+
+            wr(SPI1_CR2, rd(SPI1_CR2) & !(CR2_RXDMAEN | CR2_TXDMAEN));
+            wr(sxcr(RX_STREAM), 0);
+            wr(sxcr(TX_STREAM), 0);
+            wr(DMA2_LIFCR, RX_FLAGS | TX_FLAGS);
+
+            // ...and nothing may read the RX buffer before the DMA is done.
+            compiler_fence(Ordering::Acquire);
+
+            result
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn short_and_uneven_operations_stay_on_the_pio_path() {
+            let mut rx = [0u8; THRESHOLD];
+
+            assert!(!eligible(&Operation::Write(&[0; THRESHOLD - 1])));
+            assert!(eligible(&Operation::Write(&[0; THRESHOLD])));
+            assert!(!eligible(&Operation::Transfer(&mut rx, &[0; THRESHOLD + 1])));
+            assert!(!eligible(&Operation::DelayNs(1_000)));
+        }
+
+        #[test]
+        fn stream_words_select_channel_3_and_direction() {
+            assert_eq!(stream_cr(true, true), SXCR_CHSEL_3 | SXCR_DIR_M2P | SXCR_MINC);
+            assert_eq!(stream_cr(false, false), SXCR_CHSEL_3);
+            assert_eq!(sxcr(TX_STREAM), 0x4002_6458);
+        }
+    }
+}
+
+/// SPI1 and the GPIO/RCC registers around it, modelled per test thread
+/// for the host tests.  TXE is always set; each DR write clocks in the
+/// next of `replies` (0 once they run out), which RXNE then reports until
+/// DR is read.  Setting CRCNEXT clocks one more frame, the slave's CRC.
+/// Any other register just holds what was last written.
+#[cfg(test)]
+mod fake {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::collections::{HashMap, VecDeque};
+
+    #[derive(Default)]
+    pub(super) struct Spi1 {
+        regs: HashMap<u32, u32>,
+        /// Every register write, DR included, in order.
+        pub writes: Vec<(u32, u32)>,
+        /// Frames written to DR.
+        pub tx: Vec<u16>,
+        /// The slave's answer to each frame, in order.
+        pub replies: VecDeque<u16>,
+        /// Frames clocked in and not yet read from DR.
+        inbound: VecDeque<u16>,
+        /// Frames since CRCEN was last cleared, i.e. under the running CRC.
+        crc_frames: usize,
+        /// `crc_frames` at each CRCNEXT: how many frames each CRC covered.
+        pub crc_spans: Vec<usize>,
+        /// SR reads that still report BSY after each DR write.
+        pub busy_polls: u32,
+        /// What's left of `busy_polls` for the frame now shifting.
+        busy: u32,
+        /// Register writes made while BSY was set.
+        pub busy_writes: Vec<(u32, u32)>,
+    }
+
+    thread_local! {
+        static SPI1: RefCell<Spi1> = RefCell::new(Spi1::default());
+    }
+
+    /// Start the calling test from reset, with the slave answering `replies`.
+    pub(super) fn reset(replies: &[u16]) {
+        SPI1.with(|s| {
+            *s.borrow_mut() = Spi1 { replies: replies.iter().copied().collect(), ..Spi1::default() };
+        });
+    }
+
+    /// Inspect or poke the model.
+    pub(super) fn with<R>(f: impl FnOnce(&mut Spi1) -> R) -> R {
+        SPI1.with(|s| f(&mut s.borrow_mut()))
+    }
+
+    pub(super) fn read(addr: u32) -> u32 {
+        with(|s| match addr {
+            SPI1_SR => {
+                let rxne = if s.inbound.is_empty() { 0 } else { SR_RXNE };
+                let bsy = if s.busy > 0 { SR_BSY } else { 0 };
+                s.busy = s.busy.saturating_sub(1);
+                SR_TXE | rxne | bsy | (s.reg(SPI1_SR) & SR_CRCERR)
+            }
+            SPI1_DR => s.inbound.pop_front().unwrap_or(0) as u32,
+            _ => s.reg(addr),
+        })
+    }
+
+    pub(super) fn write(addr: u32, val: u32) {
+        with(|s| {
+            s.writes.push((addr, val));
+            if s.busy > 0 {
+                s.busy_writes.push((addr, val));
+            }
+            match addr {
+                SPI1_DR => {
+                    s.busy = s.busy_polls;
+                    s.tx.push(val as u16);
+                    s.crc_frames += 1;
+                    let reply = s.replies.pop_front().unwrap_or(0);
+                    s.inbound.push_back(reply);
+                }
+                SPI1_CR1 => {
+                    if val & CR1_CRCEN == 0 {
+                        s.crc_frames = 0;
+                    }
+                    if val & CR1_CRCNEXT != 0 {
+                        s.crc_spans.push(s.crc_frames);
+                        s.inbound.push_back(0);
+                    }
+                    // CRCNEXT clears itself once the CRC is out.
+                    s.regs.insert(addr, val & !CR1_CRCNEXT);
+                }
+                // Status flags are rc_w0: writing 0 clears, 1 leaves alone.
+                SPI1_SR => {
+                    let sr = s.reg(SPI1_SR) & val;
+                    s.regs.insert(addr, sr);
+                }
+                _ => {
+                    s.regs.insert(addr, val);
+                }
+            }
+        })
+    }
+
+    impl Spi1 {
+        pub(super) fn reg(&self, addr: u32) -> u32 {
+            self.regs.get(&addr).copied().unwrap_or(0)
+        }
+
+        pub(super) fn set(&mut self, addr: u32, val: u32) {
+            self.regs.insert(addr, val);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hardware_nss_sets_ssoe_and_drops_software_slave_management() {
+        let (cr1, cr2) = control_words(&Stm32SpiConfig { nss: Nss::Hardware, ..Default::default() });
+
+        assert_ne!(cr2 & CR2_SSOE, 0);
+        assert_eq!(cr1 & (CR1_SSM | CR1_SSI), 0);
+        assert_ne!(cr1 & CR1_MSTR, 0);
+    }
+
+    #[test]
+    fn bidi_direction_follows_write_then_read() {
+        let mut rx = [0u8; 2];
+        let ops = [Operation::Write(&[0x03, 0x10]), Operation::Read(&mut rx)];
+        let mut cr1 = CR1_BIDIMODE;
+
+        cr1 = with_direction(cr1, bidi_direction(&ops[0]).unwrap().unwrap());
+        assert_ne!(cr1 & CR1_BIDIOE, 0);
+
+        cr1 = with_direction(cr1, bidi_direction(&ops[1]).unwrap().unwrap());
+        assert_eq!(cr1 & CR1_BIDIOE, 0);
+        assert_ne!(cr1 & CR1_BIDIMODE, 0);
+    }
+
+    #[test]
+    fn a_bidi_transaction_turns_the_line_around_between_phases() {
+        fake::reset(&[0x00, 0x00, 0xAB, 0xCD]);
+        fake::with(|s| s.set(SPI1_CR1, CR1_BIDIMODE | CR1_BIDIOE | CR1_SPE));
+        let mut dev = Stm32Spi1Device { bidi: true, ..software_dev() };
+        let mut rx = [0u8; 2];
+
+        let mut ops = [Operation::Write(&[0x03, 0x10]), Operation::Read(&mut rx)];
+        SpiDevice::<u8>::transaction(&mut dev, &mut ops).unwrap();
+
+        assert_eq!(rx, [0xAB, 0xCD]);
+        let spi_writes: Vec<_> =
+            fake::with(|s| s.writes.iter().copied().filter(|&(addr, _)| addr != GPIOA_BSRR).collect());
+        let out = CR1_BIDIMODE | CR1_BIDIOE | CR1_SPE;
+        assert_eq!(
+            spi_writes,
+            [
+                (SPI1_CR1, out),
+                (SPI1_DR, 0x03),
+                (SPI1_DR, 0x10),
+                (SPI1_CR1, out & !CR1_BIDIOE),
+                (SPI1_DR, 0x00),
+                (SPI1_DR, 0x00),
+            ]
+        );
+    }
+
+    #[test]
+    fn bidi_rejects_full_duplex_operations() {
+        let mut buf = [0u8; 2];
+
+        assert!(bidi_direction(&Operation::TransferInPlace(&mut buf)).is_err());
+    }
+
+    #[derive(Default)]
+    struct CountingDelay {
+        calls: Vec<u32>,
+    }
+
+    impl DelayNs for CountingDelay {
+        fn delay_ns(&mut self, ns: u32) {
+            self.calls.push(ns);
+        }
+    }
+
+    #[test]
+    fn with_delay_routes_delays_to_the_injected_impl() {
+        fake::reset(&[]);
+        let mut dev = software_dev().with_delay(CountingDelay::default());
+
+        let mut ops = [Operation::Write(&[0x01]), Operation::DelayNs(250), Operation::Write(&[0x02])];
+        SpiDevice::<u8>::transaction(&mut dev, &mut ops).unwrap();
+
+        assert_eq!(dev.delay.calls, [250]);
+        assert_eq!(fake::with(|s| s.tx.clone()), [0x01, 0x02]);
+    }
+
+    #[test]
+    fn spin_delay_cycles_round_up_at_the_core_clock() {
+        let delay = SpinDelay::default();
+
+        assert_eq!(delay.cycles(0), 0);
+        assert_eq!(delay.cycles(1), 1);
+        assert_eq!(delay.cycles(1_000), 16);
+        assert_eq!(SpinDelay::new(168_000_000).cycles(u32::MAX), 721_554_506);
+    }
+
+    #[test]
+    fn clocks_gate_gpioa_on_ahb1_and_spi1_on_apb2() {
+        assert_eq!(CLOCK_GATES[..2], [(0x4002_3830, 1 << 0), (0x4002_3844, 1 << 12)]);
+        #[cfg(not(feature = "dma"))]
+        assert_eq!(CLOCK_GATES.len(), 2);
+    }
+
+    #[cfg(feature = "dma")]
+    #[test]
+    fn dma_builds_also_gate_dma2_on_ahb1() {
+        assert_eq!(CLOCK_GATES[2..], [(0x4002_3830, 1 << 22)]);
+    }
+
+    #[test]
+    fn gpio_routes_pa5_to_pa7_to_af5_and_leaves_other_pins() {
+        let moder = gpio_moder(0xFFFF_0003, Nss::Software, 4);
+        let afrl = gpio_afrl(0x0000_000F, Nss::Software);
+
+        assert_eq!(moder, 0xFFFF_A903);
+        assert_eq!(afrl, 0x5550_000F);
+    }
+
+    #[test]
+    fn software_cs_is_push_pull() {
+        assert_eq!(gpio_otyper(0xFFFF, 4), 0xFFEF);
+        assert_eq!(gpio_otyper(0, 4), 0);
+    }
+
+    #[test]
+    fn hardware_nss_puts_pa4_on_af5_too() {
+        // cs_pin only applies to software NSS.
+        assert_eq!(gpio_moder(0, Nss::Hardware, 9), 0x0000_AA00);
+        assert_eq!(gpio_afrl(0, Nss::Hardware), 0x5555_0000);
+    }
+
+    #[test]
+    fn default_config_matches_the_original_init() {
+        let (cr1, cr2) = control_words(&Stm32SpiConfig::default());
+
+        assert_eq!(cr1, CR1_MSTR | CR1_SSM | CR1_SSI | (0b111 << CR1_BR_SHIFT));
+        // Bit 6 is RXNEIE on the F4, not FRXTH: polled configs leave it clear.
+        assert_eq!(cr2, 0);
+    }
+
+    #[test]
+    fn config_sets_mode_prescaler_and_bit_order_bits() {
+        let cfg = Stm32SpiConfig {
+            mode: embedded_hal::spi::MODE_3,
+            prescaler: Prescaler::Div8,
+            bit_order: BitOrder::LsbFirst,
+            ..Default::default()
+        };
+
+        let (cr1, _) = control_words(&cfg);
+
+        assert_eq!(cr1 & (CR1_CPOL | CR1_CPHA), CR1_CPOL | CR1_CPHA);
+        assert_eq!((cr1 >> CR1_BR_SHIFT) & 0b111, 0b010);
+        assert_ne!(cr1 & CR1_LSBFIRST, 0);
+    }
+
+    #[test]
+    fn software_cs_on_another_pin_becomes_that_pins_output() {
+        assert_eq!(gpio_moder(0, Nss::Software, 9), 0x0004_A800);
+        assert_eq!(gpio_otyper(1 << 9, 9), 0);
+    }
+
+    #[test]
+    fn modf_in_the_status_word_is_a_mode_fault() {
+        use embedded_hal::spi::Error as _;
+
+        assert_eq!(sr_fault(SR_TXE | SR_RXNE), Ok(()));
+        assert_eq!(sr_fault(SR_TXE | SR_MODF), Err(Stm32SpiError::ModeFault));
+        assert_eq!(Stm32SpiError::ModeFault.kind(), ErrorKind::ModeFault);
+    }
+
+    /// Step an exchange of `tx` through `statuses`, one SR read per step,
+    /// until it stops blocking.  Returns the result, the bytes written to
+    /// "DR" and how many times MODF was cleared.
+    fn run_steps(statuses: &[u32], tx: u8) -> (nb::Result<u8, Stm32SpiError>, Vec<u8>, u32) {
+        let mut sent = false;
+        let mut written = Vec::new();
+        let mut cleared = 0;
+        let mut result = Err(nb::Error::WouldBlock);
+
+        for &sr in statuses {
+            result = exchange_step(&mut sent, sr, || written.push(tx), || 0xA5, || cleared += 1);
+            if !matches!(result, Err(nb::Error::WouldBlock)) {
+                break;
+            }
+        }
+        (result, written, cleared)
+    }
+
+    #[test]
+    fn a_step_blocks_until_txe_then_rxne() {
+        let (result, written, cleared) = run_steps(&[0, SR_TXE, SR_TXE, 0, SR_RXNE], 0x3C);
+
+        assert_eq!(result, Ok(0xA5));
+        assert_eq!(written, [0x3C]);
+        assert_eq!(cleared, 0);
+    }
+
+    #[test]
+    fn modf_mid_wait_clears_and_fails_instead_of_spinning() {
+        let (result, written, cleared) = run_steps(&[0, 0, SR_MODF, SR_TXE], 0x3C);
+
+        assert_eq!(result, Err(nb::Error::Other(Stm32SpiError::ModeFault)));
+        assert_eq!(written, []);
+        assert_eq!(cleared, 1);
+    }
+
+    #[test]
+    fn a_ready_flag_wins_over_modf_and_skips_the_clear() {
+        let (result, written, cleared) = run_steps(&[SR_TXE | SR_MODF, SR_RXNE | SR_MODF], 0x3C);
+
+        assert_eq!(result, Ok(0xA5));
+        assert_eq!(written, [0x3C]);
+        assert_eq!(cleared, 0);
+    }
+
+    #[test]
+    fn exchange_returns_the_byte_read_once_rxne_sets() {
+        let mut statuses = [0, 0, SR_TXE, SR_BSY, SR_BSY | SR_RXNE].into_iter();
+        let mut written = Vec::new();
+
+        let got = exchange(|| statuses.next().unwrap(), || written.push(0x9C), || 0x42u8, || {});
+
+        assert_eq!(got, Ok(0x42));
+        assert_eq!(written, [0x9C]);
+        assert_eq!(statuses.next(), None);
+    }
+
+    #[test]
+    fn exchange_fails_on_modf_before_txe() {
+        let mut statuses = [0, SR_MODF].into_iter();
+        let mut cleared = 0;
+
+        let got = exchange(|| statuses.next().unwrap(), || {}, || 0u8, || cleared += 1);
+
+        assert_eq!(got, Err(Stm32SpiError::ModeFault));
+        assert_eq!(cleared, 1);
+    }
+
+    #[test]
+    fn try_transfer_byte_rejects_a_16bit_device() {
+        let mut dev = Stm32Spi1Device { frame_16bit: true, ..software_dev() };
+
+        assert_eq!(dev.try_transfer_byte(0x00), Err(nb::Error::Other(Stm32SpiError::FrameSize)));
+    }
+
+    #[test]
+    fn cs_polarity_picks_the_bsrr_set_or_reset_half() {
+        assert_eq!(cs_bsrr(4, CsPolarity::ActiveLow, true), 1 << 20);
+        assert_eq!(cs_bsrr(4, CsPolarity::ActiveLow, false), 1 << 4);
+        assert_eq!(cs_bsrr(4, CsPolarity::ActiveHigh, true), 1 << 4);
+        assert_eq!(cs_bsrr(4, CsPolarity::ActiveHigh, false), 1 << 20);
+    }
+
+    #[test]
+    fn flush_waits_out_bsy_after_txe() {
+        let mut status = [SR_BSY, SR_TXE | SR_BSY, SR_TXE | SR_BSY, SR_TXE, SR_TXE].into_iter();
+
+        drain(|| status.next().unwrap());
+
+        // Stopped at the first idle status, not before.
+        assert_eq!(status.next(), Some(SR_TXE));
+        assert_eq!(status.next(), None);
+    }
+
+    /// Drive an `IrqTransfer` of `tx` through one interrupt per status in
+    /// `statuses`, with a slave that answers each byte with its complement.
+    /// Returns the interrupt enables after each step.
+    fn run_irq(transfer: &mut irq::IrqTransfer<8>, tx: &[u8], statuses: &[u32]) -> Vec<u32> {
+        let shift = core::cell::Cell::new(0u8);
+        transfer.load(tx).unwrap();
+        statuses
+            .iter()
+            .map(|&sr| transfer.advance(sr, || !shift.get(), |b| shift.set(b)))
+            .collect()
+    }
+
+    #[test]
+    fn the_interrupt_state_machine_moves_one_byte_per_txe_rxne_pair() {
+        use irq::{CR2_RXNEIE, CR2_TXEIE};
+
+        let mut transfer = irq::IrqTransfer::<8>::new();
+        let both = CR2_TXEIE | CR2_RXNEIE;
+
+        // TXE, then TXE again while the byte shifts (ignored), then RXNE.
+        let enables = run_irq(&mut transfer, &[0x0F, 0xA0], &[SR_TXE, SR_TXE, SR_RXNE, SR_TXE, SR_RXNE]);
+
+        assert_eq!(enables, [CR2_RXNEIE, CR2_RXNEIE, both, CR2_RXNEIE, 0]);
+        assert_eq!(transfer.result(), Some(Ok(())));
+        assert_eq!(transfer.received(), [0xF0, 0x5F]);
+    }
+
+    #[test]
+    fn an_idle_interrupt_drains_rxne_and_masks_itself() {
+        use irq::{CR2_RXNEIE, CR2_TXEIE};
+
+        fake::reset(&[0x42]);
+        unsafe { wr(SPI1_DR, 0x00) };
+        fake::with(|s| s.set(SPI1_CR2, CR2_SSOE | CR2_RXNEIE | CR2_TXEIE));
+
+        irq::on_spi1_interrupt();
+
+        assert_eq!(unsafe { rd(SPI1_SR) } & SR_RXNE, 0);
+        assert_eq!(fake::with(|s| s.reg(SPI1_CR2)), CR2_SSOE);
+    }
+
+    #[test]
+    fn the_interrupt_state_machine_stops_on_modf_and_rejects_oversize() {
+        let mut transfer = irq::IrqTransfer::<8>::new();
+
+        let enables = run_irq(&mut transfer, &[0x01], &[SR_MODF]);
+
+        assert_eq!(enables, [0]);
+        assert_eq!(transfer.result(), Some(Err(Stm32SpiError::ModeFault)));
+        assert_eq!(transfer.load(&[0; 9]), Err(Stm32SpiError::TooLong));
+    }
+
+    #[test]
+    fn software_nss_keeps_ssoe_clear() {
+        let (cr1, cr2) = control_words(&Stm32SpiConfig::default());
+
+        assert_eq!(cr2 & CR2_SSOE, 0);
+        assert_eq!(cr1 & (CR1_SSM | CR1_SSI), CR1_SSM | CR1_SSI);
+    }
+
+    #[test]
+    fn frame_16bit_sets_dff_and_leaves_cr2_alone() {
+        let cfg = Stm32SpiConfig { frame_16bit: true, nss: Nss::Hardware, ..Default::default() };
+
+        let (cr1, cr2) = control_words(&cfg);
+
+        assert_ne!(cr1 & CR1_DFF, 0);
+        assert_eq!(cr2, CR2_SSOE);
+    }
+
+    #[test]
+    fn the_builder_composes_every_field_into_cr1_and_cr2() {
+        let cfg = Stm32SpiConfig::default()
+            .with_mode(embedded_hal::spi::MODE_3)
+            .with_prescaler(Prescaler::Div8)
+            .with_bit_order(BitOrder::LsbFirst)
+            .with_nss(Nss::Hardware)
+            .with_frame_16bit(true);
+
+        let (cr1, cr2) = control_words(&cfg);
+
+        // MSTR | BR=0b010 | CPOL | CPHA | LSBFIRST | DFF, no SSM/SSI.
+        assert_eq!(cr1, 0x0897);
+        assert_eq!(cr2, CR2_SSOE);
+    }
+
+    #[test]
+    fn crc_config_writes_crcpr_before_enabling_crcen() {
+        let writes: Vec<_> = config_writes(&Stm32SpiConfig::default().with_crc_poly(0x07)).collect();
+
+        assert_eq!(writes[0], (SPI1_CRCPR, 0x07));
+        assert_eq!(writes[1].0, SPI1_CR1);
+        assert_ne!(writes[1].1 & CR1_CRCEN, 0);
+
+        let plain: Vec<_> = config_writes(&Stm32SpiConfig::default()).collect();
+        assert_eq!(plain.iter().map(|w| w.0).collect::<Vec<_>>(), [SPI1_CR1, SPI1_CR2]);
+        assert_eq!(plain[0].1 & CR1_CRCEN, 0);
+    }
+
+    #[test]
+    fn crcerr_in_the_status_word_is_a_crc_error() {
+        use embedded_hal::spi::Error as _;
+
+        assert_eq!(crc_status(SR_TXE | SR_RXNE), Ok(()));
+        assert_eq!(crc_status(SR_TXE | SR_CRCERR), Err(Stm32SpiError::Crc));
+        assert_eq!(Stm32SpiError::Crc.kind(), ErrorKind::Other);
+    }
+
+    #[test]
+    fn crcnext_is_due_after_the_last_frame_of_the_transaction() {
+        let mut rx = [0u8; 1];
+        let ops = [Operation::Write(&[1, 2]), Operation::DelayNs(10), Operation::Read(&mut rx)];
+        let mut left: usize = ops.iter().map(frames).sum();
+
+        let due: Vec<_> = (0..3).map(|_| last_frame(&mut left)).collect();
+
+        assert_eq!(due, [false, false, true]);
+        // Without CRC nothing is counted, so CRCNEXT is never due.
+        assert!(!last_frame(&mut 0));
+    }
+
+    #[test]
+    fn crcnext_follows_the_last_frame_of_an_uneven_transfer() {
+        fake::reset(&[]);
+        fake::with(|s| s.set(SPI1_CR1, CR1_CRCEN | CR1_SPE));
+        let mut dev = Stm32Spi1Device { crc: true, ..software_dev() };
+        let mut rx = [0u8; 3];
+
+        let mut ops = [Operation::Write(&[0x01, 0x02]), Operation::Transfer(&mut rx, &[0x03])];
+        assert_eq!(ops.iter().map(frames).sum::<usize>(), 5);
+        SpiDevice::<u8>::transaction(&mut dev, &mut ops).unwrap();
+
+        let (tx, spans) = fake::with(|s| (s.tx.clone(), s.crc_spans.clone()));
+        assert_eq!(tx, [0x01, 0x02, 0x03, 0x00, 0x00]);
+        assert_eq!(spans, [5]);
+    }
+
+    #[cfg(feature = "interrupt")]
+    #[test]
+    fn streamed_rx_fills_the_buffer_while_tx_runs_ahead() {
+        use irq::stream::{Feed, RX_QUEUE, queue_rx};
+
+        let mut queue = heapless::spsc::Queue::<u8, RX_QUEUE>::new();
+        let (mut producer, mut consumer) = queue.split();
+        let mut buf = [0x10, 0x20, 0x30];
+        let mut feed = Feed::new(&mut buf);
+        let mut wire = Vec::new();
+
+        // The "ISR" answers each byte with byte + 1, but only once the next
+        // one is in DR, so TX runs a byte ahead of RX until the last.
+        let mut answered = 0;
+        while !feed.step(SR_TXE, |b| wire.push(b), &mut consumer).unwrap() {
+            let tx_done = wire.len() == 3;
+            if answered < wire.len() && (answered + 1 < wire.len() || tx_done) {
+                let b = wire[answered];
+                queue_rx(SR_RXNE, || b + 1, &mut producer).unwrap();
+                answered += 1;
+            }
+        }
+
+        assert_eq!(wire, [0x10, 0x20, 0x30]);
+        assert_eq!(buf, [0x11, 0x21, 0x31]);
+    }
+
+    #[cfg(feature = "interrupt")]
+    #[test]
+    fn a_full_rx_queue_drops_the_byte_and_reports_overflow() {
+        use irq::stream::{RX_QUEUE, queue_rx};
+
+        let mut queue = heapless::spsc::Queue::<u8, RX_QUEUE>::new();
+        let (mut producer, _consumer) = queue.split();
+        for _ in 0..RX_QUEUE - 1 {
+            queue_rx(SR_RXNE, || 0xAA, &mut producer).unwrap();
+        }
+
+        assert_eq!(queue_rx(SR_TXE, || unreachable!(), &mut producer), Ok(()));
+        assert_eq!(queue_rx(SR_RXNE, || 0xBB, &mut producer), Err(Stm32SpiError::RxOverflow));
+    }
+
+    /// An 8-bit software-NSS handle built without touching SPI1, for tests
+    /// that must never reach the registers.
+    fn software_dev() -> Stm32Spi1Device {
+        Stm32Spi1Device {
+            nss: Nss::Software,
+            bidi: false,
+            frame_16bit: false,
+            cs_pin: 4,
+            cs_polarity: CsPolarity::ActiveLow,
+            crc: false,
+            delay: SpinDelay::default(),
+            sent: false,
+        }
+    }
+
+    #[test]
+    fn an_empty_transaction_never_touches_cs() {
+        fake::reset(&[]);
+        let mut dev = software_dev();
+
+        assert_eq!(SpiDevice::<u8>::transaction(&mut dev, &mut []), Ok(()));
+        assert_eq!(fake::with(|s| s.writes.len()), 0);
+    }
+
+    #[test]
+    fn an_uneven_transfer_clocks_the_longer_slice() {
+        fake::reset(&[0x11, 0x22, 0x33]);
+        let mut dev = software_dev();
+        let mut rx = [0u8; 3];
+
+        SpiDevice::<u8>::transfer(&mut dev, &mut rx, &[0xA1]).unwrap();
+
+        assert_eq!(fake::with(|s| s.tx.clone()), [0xA1, 0x00, 0x00]);
+        assert_eq!(rx, [0x11, 0x22, 0x33]);
+
+        fake::reset(&[0x11, 0x22, 0x33]);
+        let mut rx = [0u8; 1];
+
+        SpiDevice::<u8>::transfer(&mut dev, &mut rx, &[0xA1, 0xA2, 0xA3]).unwrap();
+
+        assert_eq!(fake::with(|s| s.tx.clone()), [0xA1, 0xA2, 0xA3]);
+        assert_eq!(rx, [0x11]);
+    }
+
+    #[test]
+    fn raw_padded_gets_every_reply_word_from_spi1() {
+        fake::reset(&[0x00, 0x5A, 0xC3]);
+        let mut dev = crate::mock_spi::MockSpiDriver::new(software_dev());
+        let mut rx = [0u8; 3];
+
+        dev.raw_padded(&[0x05], &mut rx).unwrap();
+
+        assert_eq!(rx, [0x00, 0x5A, 0xC3]);
+        // CS went low once around all three frames.
+        let bsrr: Vec<_> = fake::with(|s| s.writes.iter().filter(|w| w.0 == GPIOA_BSRR).map(|w| w.1).collect());
+        assert_eq!(bsrr, [1 << 20, 1 << 4]);
+    }
+
+    #[test]
+    fn multi_cs_asserts_exactly_the_selected_line() {
+        let cs = [CsPin::gpioa(4), CsPin::gpioa(8), CsPin { bsrr: 0x4002_0418, ..CsPin::gpioa(2) }];
+
+        for selected in 0..cs.len() {
+            let writes: Vec<_> = select_writes(&cs, selected).collect();
+            let asserted: Vec<_> = writes.iter().filter(|&&(_, w)| w >> 16 != 0).collect();
+
+            assert_eq!(writes.len(), cs.len());
+            assert_eq!(asserted, [&(cs[selected].bsrr, 1 << (16 + cs[selected].pin))]);
+            // The assert comes last, after every other slave is released.
+            assert_eq!(writes.last(), asserted.first().copied());
+        }
+    }
+
+    #[test]
+    fn multi_cs_waits_for_bsy_to_clear_before_releasing_cs() {
+        fake::reset(&[]);
+        fake::with(|s| s.busy_polls = 3);
+        let mut dev = MultiCsDevice::new(software_dev(), [CsPin::gpioa(4), CsPin::gpioa(8)]).unwrap();
+
+        dev.select(1).unwrap();
+        dev.write(&[0x01, 0x02]).unwrap();
+
+        let (writes, busy_writes) = fake::with(|s| (s.writes.clone(), s.busy_writes.clone()));
+        assert_eq!(writes.last(), Some(&(GPIOA_BSRR, 1 << 8)));
+        assert!(!busy_writes.iter().any(|&(addr, _)| addr == GPIOA_BSRR));
+    }
+
+    #[test]
+    fn multi_cs_rejects_a_hardware_nss_device() {
+        fake::reset(&[]);
+        let spi = Stm32Spi1Device { nss: Nss::Hardware, ..software_dev() };
+
+        assert!(matches!(MultiCsDevice::new(spi, [CsPin::gpioa(4)]), Err(Stm32SpiError::HardwareNss)));
+        assert!(fake::with(|s| s.writes.is_empty()));
+    }
+
+    #[test]
+    fn multi_cs_rejects_an_out_of_range_select() {
+        fake::reset(&[]);
+        let mut dev = MultiCsDevice::new(software_dev(), [CsPin::gpioa(4), CsPin::gpioa(8)]).unwrap();
+
+        assert_eq!(dev.select(2), Err(Stm32SpiError::NoSuchCs));
+        assert_eq!(dev.selected(), 0);
+    }
+
+    #[test]
+    fn an_active_high_cs_pin_asserts_through_the_set_half() {
+        let cs = [CsPin::gpioa(4), CsPin::gpioa(8).with_polarity(CsPolarity::ActiveHigh)];
+
+        assert_eq!(select_writes(&cs, 1).collect::<Vec<_>>(), [(GPIOA_BSRR, 1 << 4), (GPIOA_BSRR, 1 << 8)]);
+        assert_eq!(select_writes(&cs, 0).collect::<Vec<_>>(), [(GPIOA_BSRR, 1 << 24), (GPIOA_BSRR, 1 << 20)]);
+    }
+
+    #[test]
+    fn multi_cs_restarts_the_crc_for_every_transaction() {
+        fake::reset(&[]);
+        fake::with(|s| s.set(SPI1_CR1, CR1_CRCEN | CR1_SPE));
+        let spi = Stm32Spi1Device { crc: true, ..software_dev() };
+        let mut dev = MultiCsDevice::new(spi, [CsPin::gpioa(4), CsPin::gpioa(8)]).unwrap();
+
+        dev.write(&[0x01, 0x02]).unwrap();
+        dev.select(1).unwrap();
+        dev.write(&[0x03]).unwrap();
+
+        // Each CRC covers only its own transaction's frames.
+        assert_eq!(fake::with(|s| s.crc_spans.clone()), [2, 1]);
+    }
+
+    #[test]
+    fn words_of_the_wrong_width_are_rejected_before_touching_spi1() {
+        let mut dev = software_dev();
+
+        assert_eq!(SpiDevice::<u16>::write(&mut dev, &[0x1234]), Err(Stm32SpiError::FrameSize));
+    }
+
+    #[test]
+    fn transfer_word_moves_whole_halfwords_on_a_16bit_device() {
+        fake::reset(&[0xBEEF]);
+        let mut dev = Stm32Spi1Device { frame_16bit: true, ..software_dev() };
+        let mut word = [0x1234u16];
+
+        SpiDevice::<u16>::transfer_in_place(&mut dev, &mut word).unwrap();
+
+        assert_eq!(word, [0xBEEF]);
+        assert_eq!(fake::with(|s| s.tx.clone()), [0x1234]);
+    }
+
+    #[test]
+    fn a_16bit_device_rejects_byte_operations() {
+        let mut dev = Stm32Spi1Device { frame_16bit: true, ..software_dev() };
+
+        assert_eq!(SpiDevice::<u8>::write(&mut dev, &[0x12, 0x34]), Err(Stm32SpiError::FrameSize));
+        assert_eq!(control_words(&Stm32SpiConfig::default()).0 & CR1_DFF, 0);
+    }
+}
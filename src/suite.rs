@@ -0,0 +1,105 @@
+//! The on-target test cases `main` runs against the mock, in order.
+//!
+//! To add a case, write a `fn(&mut Driver, &mut dyn Output) -> bool` and
+//! list it in `TESTS`.  `run` prints `[PASS] name` / `[FAIL] name` and
+//! tallies the result; a failing case prints its own details to the
+//! runner's output first, indented.
+
+use crate::mock_spi::{Error, MockSpiDriver};
+use crate::output::Output;
+use crate::report::{Hex, TestRunner, print_mismatch};
+
+/// SPI1 on target; the host tests and the `host-stub` build run the same
+/// table against the stub.
+#[cfg(not(any(test, feature = "host-stub")))]
+pub type Driver = MockSpiDriver<crate::stm32_spi::Stm32Spi1Device<crate::systick::SysTickDelay>>;
+#[cfg(any(test, feature = "host-stub"))]
+pub type Driver = MockSpiDriver<crate::host_stub::ProtocolStub>;
+
+pub struct Test {
+    pub name: &'static str,
+    pub run: fn(&mut Driver, &mut dyn Output) -> bool,
+}
+
+pub static TESTS: &[Test] = &[
+    Test { name: "write_reg / read_reg", run: write_reg_read_reg },
+    Test { name: "echo", run: echo },
+];
+
+/// Run every case in `TESTS` on `dev`, tallying into `runner`.
+pub fn run<O: Output>(dev: &mut Driver, runner: &mut TestRunner<O>) {
+    for test in TESTS {
+        if (test.run)(dev, runner.out()) {
+            runner.pass(test.name);
+        } else {
+            runner.fail(test.name);
+        }
+    }
+}
+
+/// `actual == expected`, printing `  expected X, got Y` if not – the same
+/// detail `check_eq!` puts after its label.
+fn expect_eq<T: Hex + PartialEq + ?Sized>(out: &mut dyn Output, actual: &T, expected: &T) -> bool {
+    if actual == expected {
+        return true;
+    }
+    out.write_str("  ");
+    print_mismatch(out, actual, expected);
+    false
+}
+
+/// Print a driver error as the failure detail.
+fn failed(out: &mut dyn Output, e: Error) -> bool {
+    out.write_str("  ");
+    out.write_line(e.as_str());
+    false
+}
+
+fn write_reg_read_reg(dev: &mut Driver, out: &mut dyn Output) -> bool {
+    let write_val: u8 = 0xAB;
+    let reg_addr: u8 = 0x03;
+
+    match dev.write_reg(reg_addr, write_val).and_then(|()| dev.read_reg(reg_addr)) {
+        Ok(got) => expect_eq(out, &got, &write_val),
+        Err(e) => failed(out, e),
+    }
+}
+
+fn echo(dev: &mut Driver, out: &mut dyn Output) -> bool {
+    let mut echo_buf: [u8; 3] = [0x11, 0x22, 0x33];
+    let expected = echo_buf;
+
+    match dev.echo(&mut echo_buf) {
+        Ok(()) => expect_eq(out, &echo_buf, &expected),
+        Err(e) => failed(out, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host_stub::ProtocolStub;
+
+    #[test]
+    fn every_case_passes_against_the_stub() {
+        let mut dev = MockSpiDriver::new(ProtocolStub::new());
+        let mut runner = TestRunner::new(String::new());
+
+        run(&mut dev, &mut runner);
+
+        assert_eq!((runner.passed, runner.failed), (TESTS.len() as u32, 0), "{}", runner.out());
+    }
+
+    #[test]
+    fn a_failing_case_is_tallied_with_its_details() {
+        let mut stub = ProtocolStub::new();
+        stub.read_only = &[0x03];
+        let mut dev = MockSpiDriver::new(stub);
+        let mut runner = TestRunner::new(String::new());
+
+        run(&mut dev, &mut runner);
+
+        assert_eq!((runner.passed, runner.failed), (1, 1));
+        assert!(runner.out().starts_with("  expected 0xAB, got 0x00\r\n[FAIL] write_reg / read_reg\r\n"));
+    }
+}
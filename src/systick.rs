@@ -0,0 +1,129 @@
+//! SysTick-backed `DelayNs`.
+//!
+//! Cortex-M SysTick register map (System Control Space):
+//!   0xE000_E010  SYST_CSR  – control/status (ENABLE bit 0, CLKSOURCE bit 2,
+//!                            COUNTFLAG bit 16)
+//!   0xE000_E014  SYST_RVR  – reload value, 24 bits
+//!   0xE000_E018  SYST_CVR  – current value (any write clears it)
+//!
+//! The counter runs from the core clock (CLKSOURCE = 1) and is polled, not
+//! interrupt-driven.  A delay longer than one 24-bit period is split into
+//! several reloads.
+//!
+//! Unlike `SpinDelay`, the wait doesn't depend on how fast the loop body
+//! runs, only on `core_hz` being right.  It does take over SysTick for the
+//! duration, so don't use it alongside an RTOS tick.
+//!
+//! The firmware's SPI1 devices use it for `Operation::DelayNs` (see
+//! `main`).
+
+use embedded_hal::delay::DelayNs;
+
+use crate::stm32_spi::CORE_CLOCK_HZ;
+
+const SYST_CSR: u32 = 0xE000_E010;
+const SYST_RVR: u32 = 0xE000_E014;
+const SYST_CVR: u32 = 0xE000_E018;
+
+const CSR_ENABLE:    u32 = 1 << 0;
+const CSR_CLKSOURCE: u32 = 1 << 2;  // 1 = core clock, 0 = core clock / 8
+const CSR_COUNTFLAG: u32 = 1 << 16;
+
+/// SYST_RVR is 24 bits wide.
+const MAX_RELOAD: u32 = 0x00FF_FFFF;
+
+/// Polled SysTick `DelayNs`, calibrated against `core_hz`.
+#[derive(Debug, Copy, Clone)]
+pub struct SysTickDelay {
+    core_hz: u32,
+}
+
+impl SysTickDelay {
+    pub const fn new(core_hz: u32) -> Self {
+        Self { core_hz }
+    }
+
+    /// SysTick ticks in `ns` nanoseconds, rounded up.
+    const fn ticks(&self, ns: u32) -> u32 {
+        (ns as u64 * self.core_hz as u64).div_ceil(1_000_000_000) as u32
+    }
+}
+
+impl Default for SysTickDelay {
+    fn default() -> Self {
+        Self::new(CORE_CLOCK_HZ)
+    }
+}
+
+/// RVR for the next period of a delay with `remaining` ticks left: one
+/// period is `reload + 1` ticks.  Never 0 – COUNTFLAG only sets counting
+/// from 1 to 0, so a zero reload would never finish.
+const fn next_reload(remaining: u32) -> u32 {
+    if remaining > MAX_RELOAD {
+        MAX_RELOAD
+    } else if remaining < 2 {
+        1
+    } else {
+        remaining - 1
+    }
+}
+
+/// The reload values that make up a delay of `ticks`, in order.
+fn reloads(ticks: u32) -> impl Iterator<Item = u32> {
+    let mut remaining = ticks;
+    core::iter::from_fn(move || {
+        if remaining == 0 {
+            return None;
+        }
+        let reload = next_reload(remaining);
+        remaining = remaining.saturating_sub(reload + 1);
+        Some(reload)
+    })
+}
+
+impl DelayNs for SysTickDelay {
+    fn delay_ns(&mut self, ns: u32) {
+        for reload in reloads(self.ticks(ns)) {
+            unsafe {
+                core::ptr::write_volatile(SYST_RVR as *mut u32, reload);
+                // Clears the counter (and COUNTFLAG), so the count starts
+                // from `reload`.
+                core::ptr::write_volatile(SYST_CVR as *mut u32, 0);
+                core::ptr::write_volatile(SYST_CSR as *mut u32, CSR_ENABLE | CSR_CLKSOURCE);
+
+                while core::ptr::read_volatile(SYST_CSR as *const u32) & CSR_COUNTFLAG == 0 {}
+
+                core::ptr::write_volatile(SYST_CSR as *mut u32, 0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn programmed(delay: SysTickDelay, ns: u32) -> Vec<u32> {
+        reloads(delay.ticks(ns)).collect()
+    }
+
+    #[test]
+    fn common_delays_fit_one_reload_at_16_mhz() {
+        let delay = SysTickDelay::default();
+
+        assert_eq!(programmed(delay, 1_000), [15]);           // 1 µs = 16 ticks
+        assert_eq!(programmed(delay, 1_000_000), [15_999]);   // 1 ms
+        assert_eq!(programmed(delay, 100), [1]);              // 1.6 ticks, rounded up
+        assert_eq!(programmed(delay, 0), []);
+    }
+
+    #[test]
+    fn long_delays_split_into_24_bit_periods() {
+        // 1 s at 168 MHz is 168e6 ticks: ten full periods and a remainder.
+        let periods = programmed(SysTickDelay::new(168_000_000), 1_000_000_000);
+
+        assert_eq!(periods.len(), 11);
+        assert!(periods[..10].iter().all(|&r| r == MAX_RELOAD));
+        assert_eq!(periods.iter().map(|&r| r as u64 + 1).sum::<u64>(), 168_000_000);
+    }
+}
@@ -0,0 +1,451 @@
+//! Tiny polled USART – enough to print ASCII to the Renode analyzer and
+//! take bytes typed into it.
+//!
+//! STM32F4 USART register map (offsets from the peripheral base):
+//!   +0x00  SR   – status register   (RXNE is bit 5, TXE is bit 7)
+//!   +0x04  DR   – data register
+//!   +0x08  BRR  – baud-rate register
+//!   +0x0C  CR1  – control register 1
+//!
+//! `USART2` (0x4000_4400) is what the Discovery kit routes to the Renode
+//! analyzer; `USART1` (0x4001_1000) is there for boards wired differently.
+//! The free `uart_*` functions print through `USART2`.
+//!
+//! `BufferedUart` queues output in RAM and sends it on `flush()`, so a long
+//! hex dump doesn't stall the caller for every byte.
+
+#![allow(dead_code)]
+
+use heapless::Deque;
+
+use crate::output::{Output, console};
+use crate::rcc;
+
+const SR:  usize = 0x00;
+const DR:  usize = 0x04;
+const BRR: usize = 0x08;
+const CR1: usize = 0x0C;
+
+// SR bits
+const SR_RXNE: u32 = 1 << 5;
+const SR_TXE:  u32 = 1 << 7;
+
+// CR1 bits
+const CR1_RE: u32 = 1 << 2;
+const CR1_TE: u32 = 1 << 3;
+const CR1_UE: u32 = 1 << 13;
+
+/// SR reads `write_byte` waits for TXE before giving up on the byte.
+/// A working USART is ready within one character time (~1.4k core cycles
+/// at 115200 baud and 16 MHz), so this only runs out when the USART model
+/// is missing or misconfigured – e.g. a `.repl` that doesn't map it.
+pub const TXE_SPIN_LIMIT: u32 = 100_000;
+
+/// The BRR `init` has always written.  Not a real baud rate – Renode
+/// doesn't model line timing, it only wants BRR non-zero.  Real hardware
+/// should call `set_baud` after `init`.
+pub const DEFAULT_BRR: u32 = 0x36;
+
+/// BRR for `baud` from a `pclk_hz` peripheral clock, with OVER8 = 0.
+///
+/// USARTDIV = pclk / (16 × baud); BRR holds its mantissa in bits 15:4 and
+/// the fraction, in sixteenths, in bits 3:0.  That's pclk / baud in one
+/// number, rounded to the nearest sixteenth – rounding the whole value
+/// lets a fraction that rounds to 16 carry into the mantissa.
+pub const fn brr(pclk_hz: u32, baud: u32) -> u32 {
+    (pclk_hz + baud / 2) / baud
+}
+
+/// A USART addressed by its base.  All state lives in the hardware registers.
+///
+/// The base is a `usize` so host tests can point a `Uart` at a plain
+/// register block in memory.
+#[derive(Debug, Copy, Clone)]
+pub struct Uart {
+    base: usize,
+}
+
+pub const USART1: Uart = Uart::new(0x4001_1000);
+pub const USART2: Uart = Uart::new(0x4000_4400);
+
+impl Uart {
+    pub const fn new(base: usize) -> Self {
+        Self { base }
+    }
+
+    /// RCC enable register and bit for this USART, if it's one we know.
+    const fn clock_gate(&self) -> Option<(u32, u32)> {
+        match self.base {
+            0x4001_1000 => Some((rcc::APB2ENR, rcc::APB2ENR_USART1EN)),
+            0x4000_4400 => Some((rcc::APB1ENR, rcc::APB1ENR_USART2EN)),
+            _ => None,
+        }
+    }
+
+    /// Clock this USART.  Idempotent; a no-op for an unknown base.
+    pub fn enable_clock(&self) {
+        if let Some((reg, bit)) = self.clock_gate() {
+            rcc::enable(reg, bit);
+        }
+    }
+
+    /// Enable the transmitter and receiver, clocking the USART first with
+    /// the `real-hardware` feature.  No GPIO setup – Renode doesn't need it.
+    pub fn init(&self) {
+        #[cfg(feature = "real-hardware")]
+        self.enable_clock();
+
+        unsafe {
+            // BRR: non-zero so the peripheral considers itself configured
+            core::ptr::write_volatile((self.base + BRR) as *mut u32, DEFAULT_BRR);
+
+            // CR1: TE | RE | UE – transmit-enable + receive-enable + USART-enable
+            core::ptr::write_volatile((self.base + CR1) as *mut u32, CR1_TE | CR1_RE | CR1_UE);
+        }
+    }
+
+    /// Program BRR for `baud` given the USART's bus clock `pclk_hz` (APB1
+    /// for USART2, APB2 for USART1).  See `brr`.
+    pub fn set_baud(&self, pclk_hz: u32, baud: u32) {
+        unsafe {
+            core::ptr::write_volatile((self.base + BRR) as *mut u32, brr(pclk_hz, baud));
+        }
+    }
+
+    /// Send `b` once TXE is set.  If TXE doesn't come within
+    /// `TXE_SPIN_LIMIT` polls the byte is dropped: losing log output beats
+    /// hanging the whole run silently before it prints anything.
+    pub fn write_byte(&self, b: u8) {
+        unsafe {
+            let mut polls = 0;
+            while (core::ptr::read_volatile((self.base + SR) as *const u32) & SR_TXE) == 0 {
+                polls += 1;
+                if polls == TXE_SPIN_LIMIT {
+                    return;
+                }
+            }
+            core::ptr::write_volatile((self.base + DR) as *mut u32, b as u32);
+        }
+    }
+
+    /// Wait for a received byte and return it.
+    pub fn read_byte(&self) -> u8 {
+        loop {
+            if let Some(b) = self.try_read_byte() {
+                return b;
+            }
+        }
+    }
+
+    /// The received byte, or `None` if RXNE is clear.  Reading DR clears
+    /// RXNE.
+    pub fn try_read_byte(&self) -> Option<u8> {
+        unsafe {
+            if core::ptr::read_volatile((self.base + SR) as *const u32) & SR_RXNE == 0 {
+                return None;
+            }
+            Some(core::ptr::read_volatile((self.base + DR) as *const u32) as u8)
+        }
+    }
+
+    pub fn print(&self, s: &str) {
+        for b in s.bytes() {
+            self.write_byte(b);
+        }
+    }
+
+    pub fn println(&self, s: &str) {
+        self.print(s);
+        self.write_byte(b'\r');
+        self.write_byte(b'\n');
+    }
+
+    /// Print a u8 as two hex chars.
+    pub fn print_hex(&self, v: u8) {
+        const HEX: &[u8] = b"0123456789ABCDEF";
+        self.write_byte(HEX[(v >> 4) as usize]);
+        self.write_byte(HEX[(v & 0x0F) as usize]);
+    }
+
+    pub fn print_hex_slice(&self, slice: &[u8]) {
+        self.print("[");
+        for (i, &b) in slice.iter().enumerate() {
+            if i > 0 {
+                self.print(" ");
+            }
+            self.print_hex(b);
+        }
+        self.print("]");
+    }
+
+    pub fn print_u32(&self, v: u32) {
+        let mut buf = [0u8; 10];
+        for &b in format_u32(v, &mut buf) {
+            self.write_byte(b);
+        }
+    }
+
+    pub fn print_i32(&self, v: i32) {
+        if v < 0 {
+            self.write_byte(b'-');
+        }
+        // unsigned_abs() rather than -v: negating i32::MIN overflows.
+        self.print_u32(v.unsigned_abs());
+    }
+}
+
+/// `core::fmt::Write` sink, so `writeln!(USART2, "0x{:02X}", v)` works.
+/// `\n` is expanded to `\r\n` to match `println`.
+#[cfg(feature = "fmt")]
+impl core::fmt::Write for Uart {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for b in s.bytes() {
+            if b == b'\n' {
+                self.write_byte(b'\r');
+            }
+            self.write_byte(b);
+        }
+        Ok(())
+    }
+}
+
+/// A `Uart` behind an `N`-byte ring buffer.  Writes only queue; `flush()`
+/// sends everything queued.  A write to a full buffer flushes first, so
+/// nothing is dropped – that write just blocks like `Uart` would.
+pub struct BufferedUart<const N: usize> {
+    uart: Uart,
+    buf: Deque<u8, N>,
+}
+
+impl<const N: usize> BufferedUart<N> {
+    pub const fn new(uart: Uart) -> Self {
+        Self { uart, buf: Deque::new() }
+    }
+
+    pub fn write_byte(&mut self, b: u8) {
+        if let Err(b) = self.buf.push_back(b) {
+            self.flush();
+            // Can't fail: the flush just emptied the buffer.
+            let _ = self.buf.push_back(b);
+        }
+    }
+
+    pub fn print(&mut self, s: &str) {
+        for b in s.bytes() {
+            self.write_byte(b);
+        }
+    }
+
+    pub fn println(&mut self, s: &str) {
+        self.print(s);
+        self.write_byte(b'\r');
+        self.write_byte(b'\n');
+    }
+
+    /// Bytes queued and not yet sent.
+    pub fn pending(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Send everything queued through the UART, oldest first.
+    pub fn flush(&mut self) {
+        let uart = self.uart;
+        self.flush_with(|run| {
+            for &b in run {
+                uart.write_byte(b);
+            }
+        });
+    }
+
+    /// Empty the buffer through `send`, as contiguous runs (at most two,
+    /// if the ring has wrapped) – the shape a DMA transfer takes, one
+    /// stream request per run.
+    pub fn flush_with(&mut self, mut send: impl FnMut(&[u8])) {
+        let (front, back) = self.buf.as_slices();
+        for run in [front, back] {
+            if !run.is_empty() {
+                send(run);
+            }
+        }
+        self.buf.clear();
+    }
+}
+
+/// Format `v` as decimal digits into the tail of `buf`, returning them.
+/// 10 bytes is enough for `u32::MAX` (4294967295).
+pub(crate) fn format_u32(mut v: u32, buf: &mut [u8; 10]) -> &[u8] {
+    if v == 0 {
+        buf[9] = b'0';
+        return &buf[9..];
+    }
+
+    let mut i = buf.len();
+    while v > 0 {
+        i -= 1;
+        buf[i] = b'0' + (v % 10) as u8;
+        v /= 10;
+    }
+    &buf[i..]
+}
+
+// ---------------------------------------------------------------------------
+// Shorthands used by the test sequence in main.rs
+// ---------------------------------------------------------------------------
+//
+// Output goes to `output::console()` – USART2, or RTT with the `rtt`
+// feature.  Input is always USART2.
+
+/// Clock USART2 (APB1ENR bit 17).
+pub fn enable_clock() {
+    USART2.enable_clock();
+}
+
+pub fn uart_read_byte() -> u8 {
+    USART2.read_byte()
+}
+
+pub fn uart_try_read_byte() -> Option<u8> {
+    USART2.try_read_byte()
+}
+
+pub fn uart_write_byte(b: u8) {
+    console().write_ascii(b);
+}
+
+pub fn uart_print(s: &str) {
+    console().write_str(s);
+}
+
+pub fn uart_println(s: &str) {
+    console().write_line(s);
+}
+
+pub fn uart_print_hex(v: u8) {
+    const HEX: &[u8] = b"0123456789ABCDEF";
+    let mut out = console();
+    out.write_ascii(HEX[(v >> 4) as usize]);
+    out.write_ascii(HEX[(v & 0x0F) as usize]);
+}
+
+pub fn uart_print_hex_slice(slice: &[u8]) {
+    uart_print("[");
+    for (i, &b) in slice.iter().enumerate() {
+        if i > 0 {
+            uart_print(" ");
+        }
+        uart_print_hex(b);
+    }
+    uart_print("]");
+}
+
+pub fn uart_print_u32(v: u32) {
+    console().write_u32(v);
+}
+
+pub fn uart_print_i32(v: i32) {
+    if v < 0 {
+        uart_print("-");
+    }
+    uart_print_u32(v.unsigned_abs());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_u32_handles_zero_and_extremes() {
+        let mut buf = [0u8; 10];
+        assert_eq!(format_u32(0, &mut buf), b"0");
+        assert_eq!(format_u32(42, &mut buf), b"42");
+        assert_eq!(format_u32(u32::MAX, &mut buf), b"4294967295");
+        assert_eq!(format_u32(i32::MIN.unsigned_abs(), &mut buf), b"2147483648");
+    }
+
+    #[test]
+    fn write_byte_drops_the_byte_when_txe_never_sets() {
+        // SR, DR, BRR, CR1 – a USART that never becomes ready.
+        let mut block = [0u32, 0xEE, 0, 0];
+        let uart = Uart::new(block.as_mut_ptr() as usize);
+
+        uart.write_byte(b'A');
+        assert_eq!(block[1], 0xEE);
+
+        block[0] = SR_TXE;
+        Uart::new(block.as_mut_ptr() as usize).write_byte(b'A');
+        assert_eq!(block[1], b'A' as u32);
+    }
+
+    #[test]
+    fn usart_clock_gates_match_the_rcc_map() {
+        assert_eq!(USART2.clock_gate(), Some((0x4002_3840, 1 << 17)));
+        assert_eq!(USART1.clock_gate(), Some((0x4002_3844, 1 << 4)));
+        assert_eq!(Uart::new(0x2000_0000).clock_gate(), None);
+    }
+
+    #[test]
+    fn brr_matches_the_reference_manual_examples() {
+        // RM0090's baud-rate table (OVER8 = 0): USARTDIV 22.8125, 8.6875, 104.1875.
+        assert_eq!(brr(42_000_000, 115_200), 0x16D);
+        assert_eq!(brr(16_000_000, 115_200), 0x8B);
+        assert_eq!(brr(16_000_000, 9_600), 0x683);
+        // USARTDIV 3.99: the fraction rounds up to 16 and carries.
+        assert_eq!(brr(16_000_000, 250_391), 0x40);
+
+        let mut block = [0u32; 4];
+        Uart::new(block.as_mut_ptr() as usize).set_baud(42_000_000, 115_200);
+        assert_eq!(block[2], 0x16D);
+    }
+
+    #[test]
+    fn buffered_output_flushes_in_order() {
+        // SR, DR, BRR, CR1 – never touched unless the buffer overflows.
+        let mut block = [SR_TXE, 0, 0, 0];
+        let mut out = BufferedUart::<64>::new(Uart::new(block.as_mut_ptr() as usize));
+        let line = "0123456789ABCDEF 0123456789ABCDEF 0123456789";
+
+        out.print("discarded");
+        out.flush_with(|_| {});
+        out.println(line);
+        assert_eq!(out.pending(), line.len() + 2);
+
+        let mut runs = Vec::new();
+        out.flush_with(|run| runs.push(run.to_vec()));
+
+        assert_eq!(runs.concat(), format!("{line}\r\n").into_bytes());
+        assert_eq!(out.pending(), 0);
+    }
+
+    #[test]
+    fn a_full_buffer_flushes_to_the_uart_instead_of_dropping() {
+        let mut block = [SR_TXE, 0, 0, 0];
+        let mut out = BufferedUart::<4>::new(Uart::new(block.as_mut_ptr() as usize));
+
+        out.print("abcdef");
+
+        // "abcd" went out when 'e' didn't fit; DR holds the last of them.
+        assert_eq!(out.pending(), 2);
+        assert_eq!(unsafe { core::ptr::read_volatile(block.as_ptr().add(1)) }, b'd' as u32);
+    }
+
+    #[test]
+    fn bytes_written_to_dr_read_back_once_rxne_is_set() {
+        // SR, DR, BRR, CR1 – TXE set so `write_byte` doesn't wait.
+        let mut block = [SR_TXE, 0, 0, 0];
+        let sr = block.as_mut_ptr();
+        let uart = Uart::new(sr as usize);
+
+        assert_eq!(uart.try_read_byte(), None);
+
+        for b in *b"ok\n" {
+            uart.write_byte(b);
+            // Loop the transmitted byte back, as a wired-up TX/RX pair would.
+            unsafe { core::ptr::write_volatile(sr, SR_TXE | SR_RXNE) };
+
+            assert_eq!(uart.try_read_byte(), Some(b));
+            assert_eq!(uart.read_byte(), b);
+
+            unsafe { core::ptr::write_volatile(sr, SR_TXE) };
+        }
+    }
+}